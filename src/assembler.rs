@@ -0,0 +1,564 @@
+use std::collections::{HashMap, HashSet};
+use crate::batch::SourceLine;
+use crate::charset::{with_charset, Charset};
+use crate::diagnostics::{at_location, did_you_mean, Diagnostic, Severity, SourceLocation};
+use crate::global_map::GlobalMap;
+use crate::isa::{define_opcode, parse_opdef};
+use crate::listing::ListingControl;
+use crate::symbols::import_symbols;
+use crate::parse_utils::*;
+
+/// The location counter, and how far the line before moved it.
+struct Locctr {
+    loc: usize,
+    inc: usize,
+}
+
+/// Local labels, which are renamed to their qualified name before anything else sees them.
+/// `$NAME` belongs to the nearest global label above it and becomes `GLOBAL$NAME`. A numeric label
+/// `nH` can be defined any number of times; `nB` refers to the nearest definition above and `nF` to
+/// the nearest one below, and the k-th definition of `nH` is named `nH.k`.
+#[derive(Default)]
+struct LocalLabels {
+    scope: String,
+    numeric: [usize; 10],
+    /// The scope the operand of the current line sees.
+    operand_scope: String,
+    /// The digit of the `nH` the current line defines, which its operand doesn't see.
+    defined: Option<usize>,
+}
+
+impl LocalLabels {
+    /// Renames the label of a line and moves on to the scope or numeric label it defines.
+    fn define(&mut self, line_parts: &mut LineParts) {
+        let label = &line_parts.label;
+        self.defined = Self::numeric(label, 'H');
+        let scope_above = self.scope.clone();
+        if label.starts_with('$') {
+            line_parts.label = format!("{}{}", self.scope, label);
+        } else if !label.is_empty() && self.defined.is_none() {
+            self.scope = label.clone();
+        }
+        //an EQU names a value rather than a place, so its own operand stays in the scope above it
+        self.operand_scope = if line_parts.opcode.eq_ignore_ascii_case("EQU") { scope_above } else { self.scope.clone() };
+        if let Some(digit) = self.defined {
+            self.numeric[digit] += 1;
+            line_parts.label = format!("{}H.{}", digit, self.numeric[digit]);
+        }
+    }
+
+    /// The qualified name of a label used in the operand of the current line. The operand sees the
+    /// numeric labels above the line, so `1H J 1B` goes back to the 1H before it.
+    fn qualify(&self, label: &str) -> String {
+        if let Some(digit) = Self::numeric(label, 'B') {
+            let above = self.numeric[digit] - usize::from(self.defined == Some(digit));
+            if above == 0 {
+                panic!("{} refers back to {}H, but there is no {}H above", label, digit, digit)
+            }
+            format!("{}H.{}", digit, above)
+        } else if let Some(digit) = Self::numeric(label, 'F') {
+            //the line's own nH is already counted, and isn't below it
+            format!("{}H.{}", digit, self.numeric[digit] + 1)
+        } else if label.starts_with('$') {
+            format!("{}{}", self.operand_scope, label)
+        } else {
+            label.to_string()
+        }
+    }
+
+    /// The digit of `nH`, `nB` or `nF`, whichever `kind` asks for.
+    fn numeric(word: &str, kind: char) -> Option<usize> {
+        let mut chars = word.chars();
+        let digit = chars.next()?.to_digit(10)?;
+        let is_numeric = chars.next()?.eq_ignore_ascii_case(&kind) && chars.next().is_none();
+        is_numeric.then_some(digit as usize)
+    }
+}
+
+pub fn pass_one(global_map: &mut GlobalMap, lines: &[SourceLine], promoted: &HashSet<usize>) -> Vec<ASMLine> {
+    let mut locctr = Locctr { loc: 0, inc: 0 };
+    let mut local_labels = LocalLabels::default();
+    //CODEPAGE sets the character set from where it is to the end of its file
+    let mut codepages: HashMap<&str, Charset> = HashMap::new();
+    let mut asm_lines: Vec<ASMLine> = Vec::new();
+    for (line_no, source_line) in lines.iter().enumerate().map(|line| (line.0 + 1, line.1)) {
+        let mut line_parts = split_line(global_map, &source_line.text);
+        //listing directives only matter to the listing, which reads them from the source
+        if line_parts.is_empty() || at_location(&source_line.location, || ListingControl::parse(&line_parts)).is_some() {
+            continue;
+        }
+        if line_parts.opcode.eq_ignore_ascii_case("IMPORT") {
+            at_location(&source_line.location, || import_symbols(global_map, &line_parts, &source_line.location));
+            continue;
+        }
+        //pass 2 looks mnemonics up in the final instruction set, so an OPDEF can only change the ones
+        //that no line above it has used
+        if line_parts.opcode.eq_ignore_ascii_case("OPDEF") {
+            at_location(&source_line.location, || {
+                if !line_parts.label.is_empty() {
+                    panic!("OPDEF doesn't take a label")
+                }
+                let (mnemonic, detail) = parse_opdef(&line_parts.operand);
+                if let Some(used) = asm_lines.iter().find(|line| matches!(&line.opcode_spec, OpcodeSpec::Opcode(used, _) if *used == mnemonic)) {
+                    panic!("{} is already used at {}, so OPDEF can't change it after that", mnemonic, used.location)
+                }
+                define_opcode(global_map, mnemonic, detail)
+            });
+            continue;
+        }
+        if line_parts.opcode.eq_ignore_ascii_case("CODEPAGE") {
+            let charset = at_location(&source_line.location, || parse_codepage(&line_parts));
+            codepages.insert(&source_line.location.file, charset);
+            continue;
+        }
+        at_location(&source_line.location, || local_labels.define(&mut line_parts));
+        let is_first = asm_lines.is_empty();
+        let charset = codepages.get(source_line.location.file.as_str()).copied().unwrap_or(global_map.charset);
+        let asm_line = at_location(&source_line.location, || with_charset(charset, || {
+            pass_one_line(global_map, &local_labels, line_no, line_parts, is_first, &source_line.location, promoted, &mut locctr)
+        }));
+        asm_lines.push(asm_line);
+    }
+    resolve_deferred(global_map, &mut asm_lines);
+    asm_lines
+}
+
+fn parse_codepage(line_parts: &LineParts) -> Charset {
+    if !line_parts.label.is_empty() {
+        panic!("CODEPAGE doesn't take a label")
+    }
+    if line_parts.operand.is_empty() {
+        panic!("CODEPAGE needs a character set, ASCII or EBCDIC")
+    }
+    Charset::parse(&line_parts.operand)
+}
+
+/// `line_no` is the index of the line in `lines`, counting from 1, which stays unique across INCLUDEs.
+#[allow(clippy::too_many_arguments)]
+fn pass_one_line(global_map: &mut GlobalMap, local_labels: &LocalLabels, line_no: usize, line_parts: LineParts, is_first: bool, location: &SourceLocation, promoted: &HashSet<usize>, locctr: &mut Locctr) -> ASMLine {
+    let LineParts { label, opcode, operand: address, .. } = line_parts;
+    let label = global_map.fold_label(&label);
+    let (label, opcode, address) = (label.as_str(), opcode.as_str(), address.as_str());
+    let mut opcode_spec = if !opcode.is_empty() {
+        parse_opcode(global_map, opcode.to_string())
+    } else {
+        panic!("No opcode")
+    };
+    if let OpcodeSpec::Opcode(mnemonic, OpcodeFormat::Three) = &opcode_spec {
+        if promoted.contains(&line_no) {
+            opcode_spec = OpcodeSpec::Opcode(mnemonic.clone(), OpcodeFormat::Four);
+        }
+    }
+    let address_spec = if let OpcodeSpec::Opcode(mnemonic, OpcodeFormat::Two) = &opcode_spec {
+        parse_format_two_operand(global_map, mnemonic, address.to_string())
+    } else if !address.is_empty() {
+        let address_spec = match &opcode_spec {
+            OpcodeSpec::Directive(directive) if directive == "WORD" => parse_word_operand(address.to_string()),
+            OpcodeSpec::Directive(directive) if directive == "BYTE" => parse_byte_operand(address.to_string()),
+            OpcodeSpec::Directive(directive) if directive == "FILL" => parse_fill_operand(address.to_string()),
+            OpcodeSpec::Directive(directive) if directive == "ASSERT" => parse_assert_operand(address.to_string()),
+            OpcodeSpec::Directive(directive) if directive == "ERROR" || directive == "WARNING" => AddressSpec::Message(parse_quoted(directive, address)),
+            _ => parse_address(address.to_string()),
+        };
+        match address_spec {
+            AddressSpec::Label(label, mode) => AddressSpec::Label(label_name(global_map, local_labels, &label), mode),
+            AddressSpec::Condition(mut condition, message) => {
+                condition.expressions_mut().for_each(|expression| name_labels(global_map, local_labels, expression));
+                AddressSpec::Condition(condition, message)
+            }
+            address_spec => address_spec,
+        }
+    } else if matches!(&opcode_spec, OpcodeSpec::Directive(directive) if matches!(directive.as_str(), "ASSERT" | "ERROR" | "WARNING")) {
+        panic!("{} needs an operand", opcode.to_uppercase())
+    } else {
+        AddressSpec::Address(0, AddressingModes::None)
+    };
+    let address_spec = match (&opcode_spec, address_spec) {
+        (OpcodeSpec::Directive(directive), AddressSpec::Label(label, mode)) if directive == "RESB" || directive == "RESW" => {
+            //the location of every line after this one depends on the size, so it can't wait
+            let Some(&size) = global_map.label_map.get(&label) else {
+                panic!("{} {} uses a label that isn't defined yet, but the size has to be known here since it moves the location counter", directive, label)
+            };
+            if !global_map.is_absolute(&label) {
+                panic!("{} {} needs an absolute size, {} is relative", directive, label, label)
+            }
+            AddressSpec::Address(size as i32, mode)
+        }
+        (_, address_spec) => address_spec,
+    };
+    locctr.loc += locctr.inc;
+    if is_first {
+        if let OpcodeSpec::Directive(directive) = &opcode_spec {
+            if directive == "START" {
+                if let AddressSpec::Address(_, _) = &address_spec {
+                    locctr.loc = get_loc_inc(&opcode_spec, &address_spec);
+                    if !label.is_empty() {
+                        global_map.label_map.insert(label.to_string(), locctr.loc);
+                    }
+                } else {
+                    panic!("Invalid address for start")
+                }
+            }
+        } else {
+            panic!("First line should be a START")
+        }
+    } else {
+        if let OpcodeSpec::Directive(directive) = &opcode_spec {
+            if directive == "LTORG" {
+                global_map.literal_pool = Vec::new();
+                for lit in &global_map.literal_pool {
+                    global_map.literal_map.insert(lit.clone(), locctr.loc);
+                    locctr.loc += lit.get_len();
+                }
+            }
+        }
+        if let AddressSpec::Literal(literal) = &address_spec {
+            global_map.literal_pool.push(literal.clone());
+        }
+
+        if opcode_spec == OpcodeSpec::Directive(String::from("ALIGN")) {
+            let alignment = match address_spec {
+                AddressSpec::Address(alignment, _) if alignment > 0 => alignment as usize,
+                _ => panic!("ALIGN needs a positive number"),
+            };
+            locctr.loc = locctr.loc.div_ceil(alignment) * alignment;
+        }
+        locctr.inc = get_loc_inc(&opcode_spec, &address_spec);
+        if opcode_spec == OpcodeSpec::Directive(String::from("EQU")) {
+            let address_spec = define_equ(global_map, local_labels, label, address, locctr.loc);
+            return ASMLine { line_no, location: location.clone(), label: label.to_string(), loc: locctr.loc, opcode_spec, address_spec };
+        }
+        if !label.is_empty() {
+            global_map.label_map.insert(label.to_string(), locctr.loc);
+            if let AddressSpec::Constant(constant) = &address_spec {
+                global_map.constant_map.insert(label.to_string(), constant.clone());
+            }
+        }
+    }
+    ASMLine {
+        line_no,
+        location: location.clone(),
+        label: label.to_string(),
+        loc: locctr.loc,
+        opcode_spec,
+        address_spec,
+    }
+}
+
+/// Defines the label of an EQU line, returning its value as the line's operand. When the expression
+/// uses a label that isn't defined yet, the expression is kept and resolved at the end of pass 1.
+fn define_equ(global_map: &mut GlobalMap, local_labels: &LocalLabels, label: &str, operand: &str, loc: usize) -> AddressSpec {
+    if label.is_empty() {
+        panic!("EQU needs a label")
+    }
+    let mut expression = parse_expression(operand);
+    name_labels(global_map, local_labels, &mut expression);
+    global_map.equ_expressions.insert(label.to_string(), expression.clone());
+    match expression.evaluate(global_map, loc) {
+        Some((value, is_absolute)) => define_symbol(global_map, label, value, is_absolute),
+        None => AddressSpec::Expression(expression),
+    }
+}
+
+/// The name a label used in an operand is stored under, qualified if it is local.
+fn label_name(global_map: &GlobalMap, local_labels: &LocalLabels, label: &str) -> String {
+    global_map.fold_label(&local_labels.qualify(label))
+}
+
+fn name_labels(global_map: &GlobalMap, local_labels: &LocalLabels, expression: &mut Expression) {
+    for (_, term) in expression.terms.iter_mut() {
+        if let Term::Label(label) = term {
+            *label = label_name(global_map, local_labels, label);
+        }
+    }
+}
+
+fn define_symbol(global_map: &mut GlobalMap, label: &str, value: i32, is_absolute: bool) -> AddressSpec {
+    let address = usize::try_from(value).unwrap_or_else(|_| panic!("Value {} of {} can't be negative", value, label));
+    global_map.label_map.insert(label.to_string(), address);
+    if is_absolute {
+        global_map.absolute_labels.insert(label.to_string());
+    }
+    AddressSpec::Address(value, AddressingModes::None)
+}
+
+/// Resolves the EQUs that refer to labels defined after them, going over them again for as long as
+/// that defines another one. What's left is either circular or uses an undefined label, and is
+/// reported by check_symbols.
+fn resolve_deferred(global_map: &mut GlobalMap, asm_lines: &mut [ASMLine]) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for line in asm_lines.iter_mut() {
+            let AddressSpec::Expression(expression) = &line.address_spec else {
+                continue;
+            };
+            let resolved = at_location(&line.location, || expression.evaluate(global_map, line.loc));
+            if let Some((value, is_absolute)) = resolved {
+                line.address_spec = at_location(&line.location, || define_symbol(global_map, &line.label, value, is_absolute));
+                changed = true;
+            }
+        }
+    }
+}
+
+/// Runs pass 1 until no format 3 instruction is left whose target can't be reached with PC or base
+/// relative addressing, promoting those to format 4. Returns the final pass 1 along with the promoted line numbers.
+pub fn relax(global_map: &GlobalMap, lines: &[SourceLine]) -> (GlobalMap, Vec<ASMLine>, Vec<usize>) {
+    let mut promoted = HashSet::new();
+    loop {
+        let mut relaxed_map = global_map.clone();
+        let asm_lines = pass_one(&mut relaxed_map, lines, &promoted);
+        let out_of_range = get_out_of_range_lines(&relaxed_map, &asm_lines);
+        if out_of_range.is_empty() {
+            let mut promoted = promoted.into_iter().collect::<Vec<usize>>();
+            promoted.sort();
+            return (relaxed_map, asm_lines, promoted);
+        }
+        promoted.extend(out_of_range);
+    }
+}
+
+fn get_out_of_range_lines(global_map: &GlobalMap, asm_lines: &[ASMLine]) -> Vec<usize> {
+    let mut base = None;
+    let mut out_of_range = Vec::new();
+    for line in asm_lines {
+        match (&line.opcode_spec, &line.address_spec) {
+            //undefined labels are reported by check_symbols
+            (OpcodeSpec::Directive(_), AddressSpec::Label(label, _)) if !global_map.label_map.contains_key(label) => {}
+            (OpcodeSpec::Directive(directive), address_spec) if directive == "BASE" => {
+                base = Some(get_base_value(global_map, address_spec));
+            }
+            (OpcodeSpec::Directive(directive), _) if directive == "NOBASE" => {
+                base = None;
+            }
+            (OpcodeSpec::Opcode(_, OpcodeFormat::Three), AddressSpec::Label(label, _)) => {
+                let Some(&label_loc) = global_map.label_map.get(label) else {
+                    continue;
+                };
+                let pc = line.loc + 3;
+                let fits = if global_map.is_absolute(label) { label_loc < 4096 } else { get_displacement(base, pc, label_loc).is_some() };
+                if !fits {
+                    out_of_range.push(line.line_no);
+                }
+            }
+            _ => {}
+        }
+    }
+    out_of_range
+}
+
+/// Reports every label defined more than once, and every label used without being defined. Run at
+/// the end of pass 1, so that all of them are found before pass 2 stops at the first one.
+pub fn check_symbols(global_map: &GlobalMap, asm_lines: &[ASMLine]) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+    let mut defined: HashMap<&str, &SourceLocation> = HashMap::new();
+    for line in asm_lines.iter().filter(|line| !line.label.is_empty()) {
+        if let Some(imported) = global_map.imported_labels.get(&line.label) {
+            errors.push(Diagnostic::new(Severity::Error, &line.location, format!("label {} is also imported from {}", line.label, imported)));
+        }
+        if let Some(first) = defined.get(line.label.as_str()) {
+            errors.push(Diagnostic::new(Severity::Error, &line.location, format!("duplicate label {}, first defined at {}", line.label, first)));
+        } else {
+            defined.insert(&line.label, &line.location);
+        }
+    }
+    let unresolved = asm_lines.iter().filter_map(|line| match &line.address_spec {
+        AddressSpec::Expression(expression) => Some((line.label.as_str(), expression)),
+        _ => None,
+    }).collect::<HashMap<&str, &Expression>>();
+    for line in asm_lines {
+        if let AddressSpec::Expression(expression) = &line.address_spec {
+            let message = match find_cycle(&unresolved, &line.label, &mut vec![line.label.as_str()]) {
+                Some(cycle) => format!("circular definition {}", cycle.join(" -> ")),
+                None => {
+                    let missing = expression.labels().find(|label| !global_map.label_map.contains_key(*label)).unwrap();
+                    if unresolved.contains_key(missing.as_str()) {
+                        format!("can't resolve {}, since {} can't be resolved", line.label, missing)
+                    } else {
+                        format!("can't resolve {}, since {} is undefined{}", line.label, missing, did_you_mean(missing, global_map.label_map.keys()))
+                    }
+                }
+            };
+            errors.push(Diagnostic::new(Severity::Error, &line.location, message));
+        }
+        let used = match &line.address_spec {
+            AddressSpec::Label(label, _) => vec![label],
+            AddressSpec::Condition(condition, _) => condition.labels().collect(),
+            _ => Vec::new(),
+        };
+        for label in used {
+            //uses of an unresolved EQU are covered by the error on the EQU itself
+            if !global_map.label_map.contains_key(label) && !unresolved.contains_key(label.as_str()) {
+                errors.push(Diagnostic::new(Severity::Error, &line.location, format!("undefined label {}{}", label, did_you_mean(label, global_map.label_map.keys()))));
+            }
+        }
+    }
+    errors
+}
+
+/// Evaluates ASSERT, ERROR and WARNING once every symbol is known. A failed ASSERT and an ERROR are
+/// errors, a WARNING is a warning.
+pub fn check_assertions(global_map: &GlobalMap, asm_lines: &[ASMLine]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for line in asm_lines {
+        let (severity, message) = match (&line.opcode_spec, &line.address_spec) {
+            (_, AddressSpec::Condition(condition, message)) => {
+                //undefined labels are reported by check_symbols
+                if at_location(&line.location, || condition.evaluate(global_map, line.loc)) != Some(false) {
+                    continue;
+                }
+                match message {
+                    Some(message) => (Severity::Error, format!("assertion {} failed: {}", condition, message)),
+                    None => (Severity::Error, format!("assertion {} failed", condition)),
+                }
+            }
+            (OpcodeSpec::Directive(directive), AddressSpec::Message(message)) if directive == "WARNING" => (Severity::Warning, message.clone()),
+            (_, AddressSpec::Message(message)) => (Severity::Error, message.clone()),
+            _ => continue,
+        };
+        diagnostics.push(Diagnostic::new(severity, &line.location, message));
+    }
+    diagnostics
+}
+
+/// A path of unresolved EQUs from `label` back to `path[0]`, if there is one.
+fn find_cycle<'a>(unresolved: &HashMap<&'a str, &'a Expression>, label: &str, path: &mut Vec<&'a str>) -> Option<Vec<&'a str>> {
+    for next in unresolved.get(label)?.labels() {
+        let Some((&next, _)) = unresolved.get_key_value(next.as_str()) else {
+            continue;
+        };
+        if next == path[0] {
+            let mut cycle = path.clone();
+            cycle.push(next);
+            return Some(cycle);
+        }
+        if !path.contains(&next) {
+            path.push(next);
+            if let Some(cycle) = find_cycle(unresolved, next, path) {
+                return Some(cycle);
+            }
+            path.pop();
+        }
+    }
+    None
+}
+
+/// Warns about labels that differ from an earlier label only in case, which are two labels when
+/// labels are case sensitive but most likely meant to be one.
+pub fn check_label_case(asm_lines: &[ASMLine]) -> Vec<Diagnostic> {
+    let mut seen: Vec<&ASMLine> = Vec::new();
+    let mut warnings = Vec::new();
+    for line in asm_lines.iter().filter(|line| !line.label.is_empty()) {
+        let similar = seen.iter().find(|earlier| earlier.label != line.label && earlier.label.eq_ignore_ascii_case(&line.label));
+        if let Some(earlier) = similar {
+            warnings.push(Diagnostic::new(Severity::Warning, &line.location, format!("label {} differs only in case from {} defined at {}", line.label, earlier.label, earlier.location)));
+        }
+        seen.push(line);
+    }
+    warnings
+}
+
+/// Compares every BASE directive with the value loaded by the LDB before it. Only immediate loads
+/// (`LDB #LABEL`) can be checked, since any other LDB loads a value from memory.
+pub fn check_base(global_map: &GlobalMap, asm_lines: &[ASMLine]) -> Vec<Diagnostic> {
+    let mut loaded: Option<(&SourceLocation, Option<usize>)> = None;
+    let mut warnings = Vec::new();
+    for line in asm_lines {
+        match (&line.opcode_spec, &line.address_spec) {
+            (OpcodeSpec::Opcode(opcode, _), address_spec) if opcode == "LDB" => {
+                let value = match address_spec {
+                    AddressSpec::Label(label, AddressingModes::Immediate) => global_map.label_map.get(label).copied(),
+                    AddressSpec::Address(address, AddressingModes::Immediate) => usize::try_from(*address).ok(),
+                    _ => None,
+                };
+                loaded = Some((&line.location, value));
+            }
+            (OpcodeSpec::Directive(directive), address_spec) if directive == "BASE" => {
+                let base = get_base_value(global_map, address_spec);
+                match loaded {
+                    None => warnings.push(Diagnostic::new(Severity::Warning, &line.location, format!("BASE {} is declared but B was never loaded with LDB", base))),
+                    Some((ldb_location, Some(value))) if value != base => warnings.push(Diagnostic::new(Severity::Warning, &line.location, format!("BASE {} doesn't match {} loaded by LDB at {}", base, value, ldb_location))),
+                    Some((ldb_location, None)) => warnings.push(Diagnostic::new(Severity::Warning, &line.location, format!("BASE {} can't be checked against the non-immediate LDB at {}", base, ldb_location))),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    warnings
+}
+
+pub fn pass_two(global_map: &GlobalMap, asm_lines: &[ASMLine]) -> Vec<Option<ObjectCode>> {
+    let mut base = None;
+    let mut object_codes = Vec::new();
+    for line in asm_lines.iter().take(asm_lines.len() - 1) {
+        let (object_code, new_base, _) = at_location(&line.location, || get_object_code(base, line.loc, global_map, line));
+        base = new_base;
+        object_codes.push(object_code);
+    }
+    object_codes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(source: &str) -> (GlobalMap, Vec<ASMLine>) {
+        let lines = source.lines().enumerate().map(|(idx, text)| SourceLine {
+            text: text.to_string(),
+            location: SourceLocation { file: String::from("test.sic"), line_no: idx + 1 },
+        }).collect::<Vec<SourceLine>>();
+        let mut global_map = GlobalMap::init();
+        let asm_lines = pass_one(&mut global_map, &lines, &HashSet::new());
+        (global_map, asm_lines)
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let (global_map, asm_lines) = assemble(source);
+        check_symbols(&global_map, &asm_lines).into_iter().map(|diagnostic| diagnostic.message).collect()
+    }
+
+    #[test]
+    fn equ_resolves_forward_references() {
+        let (global_map, asm_lines) = assemble("PROG START 1000\nSIZE EQU BUFEND-BUF\nTWICE EQU SIZE+SIZE\nBUF RESB 10\nBUFEND EQU *\n END PROG");
+        assert_eq!(global_map.label_map["SIZE"], 10);
+        assert_eq!(global_map.label_map["TWICE"], 20);
+        assert!(global_map.is_absolute("SIZE"));
+        assert!(check_symbols(&global_map, &asm_lines).is_empty());
+    }
+
+    #[test]
+    fn local_labels_in_expressions() {
+        let (global_map, asm_lines) = assemble("PROG START 1000\nMAIN LDA $BUF\n$BUF RESB 4\n$END EQU *\n$SIZE EQU $END-$BUF\n ASSERT $END-$BUF<=4\nSIZE EQU $END-$BUF\n END PROG");
+        assert_eq!(global_map.label_map["MAIN$SIZE"], 4);
+        assert_eq!(global_map.label_map["SIZE"], 4);
+        assert_eq!(asm_lines[5].address_spec, parse_assert_operand(String::from("MAIN$END-MAIN$BUF<=4")));
+        assert!(check_symbols(&global_map, &asm_lines).is_empty());
+    }
+
+    #[test]
+    fn numeric_labels_in_expressions() {
+        let (global_map, asm_lines) = assemble("PROG START 1000\n1H LDA #1\nLEN EQU 1F-1B\n ASSERT 1F-1B=6\n1H J 1B\n1H J 1F\n1H RSUB\n END PROG");
+        assert_eq!(global_map.label_map["LEN"], 3);
+        assert_eq!(asm_lines[4].address_spec, AddressSpec::Label(String::from("1H.1"), AddressingModes::Direct));
+        assert_eq!(asm_lines[5].address_spec, AddressSpec::Label(String::from("1H.4"), AddressingModes::Direct));
+        assert!(check_symbols(&global_map, &asm_lines).is_empty());
+    }
+
+    #[test]
+    fn equ_reports_cycles() {
+        assert_eq!(errors("PROG START 0\nA EQU B+1\nB EQU A-1\n END PROG"), vec![
+            "circular definition A -> B -> A",
+            "circular definition B -> A -> B",
+        ]);
+    }
+
+    #[test]
+    fn equ_reports_undefined_labels() {
+        assert_eq!(errors("PROG START 0\nA EQU MISSING+1\nB EQU A+2\n END PROG"), vec![
+            "can't resolve A, since MISSING is undefined",
+            "can't resolve B, since A can't be resolved",
+        ]);
+    }
+}
@@ -1,9 +1,75 @@
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use crate::diagnostics::{at_location, SourceLocation};
 
-pub fn batch_mode(filename: &str) ->Vec<String>{
-    let file = File::open(filename).expect("Unable to open file");
+#[derive(Clone, Debug)]
+pub struct SourceLine {
+    pub text: String,
+    pub location: SourceLocation,
+}
+
+pub fn batch_mode(filename: &str, include_dirs: &[String]) -> Vec<SourceLine> {
+    let path = PathBuf::from(filename);
+    expand_includes(read_source(&path), include_dirs, &mut vec![canonical(&path)])
+}
+
+pub fn read_source(path: &Path) -> Vec<SourceLine> {
+    let file = File::open(path).unwrap_or_else(|_| panic!("Unable to open file {}", path.display()));
     let lines = BufReader::new(file).lines();
-    let lines =  lines.into_iter().map(|line| line.expect("Couldn't read line")).collect();
-    lines
-}
\ No newline at end of file
+    lines.into_iter().enumerate().map(|(idx, line)| SourceLine {
+        text: line.expect("Couldn't read line"),
+        location: SourceLocation { file: path.display().to_string(), line_no: idx + 1 },
+    }).collect()
+}
+
+/// Splices the lines of every `INCLUDE 'file'` into the stream. `stack` holds the files currently
+/// being included so that a file including itself, directly or not, is reported instead of looping.
+pub fn expand_includes(lines: Vec<SourceLine>, include_dirs: &[String], stack: &mut Vec<PathBuf>) -> Vec<SourceLine> {
+    let mut expanded = Vec::new();
+    for line in lines {
+        let Some(included) = at_location(&line.location, || get_included_file(&line.text)) else {
+            expanded.push(line);
+            continue;
+        };
+        let path = find_include(&included, &line.location, include_dirs)
+            .unwrap_or_else(|| panic!("{}: Couldn't find included file {}", line.location, included));
+        let canonical_path = canonical(&path);
+        if stack.contains(&canonical_path) {
+            let chain = stack.iter().chain([&canonical_path]).map(|path| path.display().to_string()).collect::<Vec<String>>();
+            panic!("{}: Include cycle {}", line.location, chain.join(" -> "))
+        }
+        stack.push(canonical_path);
+        expanded.extend(expand_includes(read_source(&path), include_dirs, stack));
+        stack.pop();
+    }
+    expanded
+}
+
+/// The file named by an `INCLUDE 'file'` line, written in any case. The name is everything between
+/// the quotes, spaces included, and only a comment may follow it.
+fn get_included_file(line: &str) -> Option<String> {
+    let line = line.trim();
+    let end = line.find(char::is_whitespace).unwrap_or(line.len());
+    if !line[..end].eq_ignore_ascii_case("INCLUDE") {
+        return None;
+    }
+    let quoted = line[end..].trim_start().strip_prefix('\'').and_then(|rest| rest.split_once('\''));
+    match quoted {
+        Some((file, rest)) if !file.is_empty() && (rest.trim().is_empty() || rest.trim_start().starts_with('.')) => Some(file.to_string()),
+        _ => panic!("INCLUDE needs a quoted file name, like INCLUDE 'file.sic'"),
+    }
+}
+
+/// Looks for an included file next to the file including it, then in each `-I` directory in order.
+pub fn find_include(included: &str, location: &SourceLocation, include_dirs: &[String]) -> Option<PathBuf> {
+    let including_dir = Path::new(&location.file).parent().map(Path::to_path_buf).unwrap_or_default();
+    std::iter::once(including_dir)
+        .chain(include_dirs.iter().map(PathBuf::from))
+        .map(|dir| dir.join(included))
+        .find(|path| path.is_file())
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
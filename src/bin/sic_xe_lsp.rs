@@ -0,0 +1,3 @@
+fn main() {
+    sic_xe_assembler::lsp::run();
+}
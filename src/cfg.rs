@@ -0,0 +1,190 @@
+use std::collections::{HashMap, VecDeque};
+use crate::batch::SourceLine;
+use crate::global_map::GlobalMap;
+use crate::parse_utils::*;
+
+const JUMPS: [&str; 6] = ["J", "JEQ", "JGT", "JLT", "JSUB", "RSUB"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeKind {
+    FallThrough,
+    Jump,
+    Call,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions entered only at the top and left only at the bottom. `lines` are indices
+/// into the pass 1 lines.
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    pub lines: Vec<usize>,
+    pub successors: Vec<Edge>,
+}
+
+/// Basic blocks, and the blocks grouped into the main program and each subroutine. Blocks that
+/// can't be reached from any entry aren't in a group.
+#[derive(Clone, Debug)]
+pub struct ControlFlowGraph {
+    pub name: String,
+    pub blocks: Vec<BasicBlock>,
+    pub routines: Vec<(String, Vec<usize>)>,
+}
+
+/// BASE and NOBASE only change how operands are encoded, so they don't interrupt a block.
+fn is_transparent(line: &ASMLine) -> bool {
+    matches!(&line.opcode_spec, OpcodeSpec::Directive(directive) if directive == "BASE" || directive == "NOBASE")
+}
+
+fn mnemonic(line: &ASMLine) -> Option<&str> {
+    match &line.opcode_spec {
+        OpcodeSpec::Opcode(mnemonic, _) => Some(mnemonic),
+        OpcodeSpec::Directive(_) => None,
+    }
+}
+
+/// Where a jump goes, if it can be known without running the program.
+fn jump_target(global_map: &GlobalMap, line: &ASMLine) -> Option<usize> {
+    match &line.address_spec {
+        AddressSpec::Label(label, AddressingModes::None | AddressingModes::Direct | AddressingModes::Immediate) => global_map.label_map.get(label).copied(),
+        AddressSpec::Address(address, AddressingModes::None | AddressingModes::Direct | AddressingModes::Immediate) => usize::try_from(*address).ok(),
+        _ => None,
+    }
+}
+
+impl ControlFlowGraph {
+    pub fn build(global_map: &GlobalMap, asm_lines: &[ASMLine]) -> Self {
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut block_at = HashMap::new();
+        let mut previous: Option<&ASMLine> = None;
+        for (idx, line) in asm_lines.iter().enumerate() {
+            if is_transparent(line) {
+                continue;
+            }
+            if mnemonic(line).is_none() {
+                previous = None;
+                continue;
+            }
+            let is_leader = match previous {
+                None => true,
+                Some(previous) => !line.label.is_empty() || JUMPS.contains(&mnemonic(previous).unwrap_or_default()),
+            };
+            if is_leader {
+                block_at.insert(line.loc, blocks.len());
+                blocks.push(BasicBlock { lines: Vec::new(), successors: Vec::new() });
+            }
+            blocks.last_mut().unwrap().lines.push(idx);
+            previous = Some(line);
+        }
+
+        for idx in 0..blocks.len() {
+            let last = *blocks[idx].lines.last().unwrap();
+            let line = &asm_lines[last];
+            let mnemonic = mnemonic(line).unwrap_or_default();
+            let target = jump_target(global_map, line).and_then(|address| block_at.get(&address).copied());
+            let mut successors = Vec::new();
+            match (mnemonic, target) {
+                ("JSUB", Some(target)) => successors.push(Edge { target, kind: EdgeKind::Call }),
+                ("RSUB", _) => {}
+                (_, Some(target)) if JUMPS.contains(&mnemonic) => successors.push(Edge { target, kind: EdgeKind::Jump }),
+                _ => {}
+            }
+            let falls_through = mnemonic != "J" && mnemonic != "RSUB";
+            let next_is_code = asm_lines[last + 1..].iter().find(|next| !is_transparent(next)).is_some_and(|next| self::mnemonic(next).is_some());
+            if falls_through && next_is_code && idx + 1 < blocks.len() {
+                successors.push(Edge { target: idx + 1, kind: EdgeKind::FallThrough });
+            }
+            blocks[idx].successors = successors;
+        }
+
+        let name = asm_lines.first().map(|line| line.label.clone()).unwrap_or_default();
+        let mut entries = Vec::new();
+        if !blocks.is_empty() {
+            entries.push(0);
+        }
+        for block in &blocks {
+            for edge in &block.successors {
+                if edge.kind == EdgeKind::Call && !entries.contains(&edge.target) {
+                    entries.push(edge.target);
+                }
+            }
+        }
+        if entries.len() > 1 {
+            entries[1..].sort();
+        }
+
+        //a routine owns every block reachable from its entry without following a call
+        let mut owner = vec![None; blocks.len()];
+        let mut routines = Vec::new();
+        for entry in entries {
+            if owner[entry].is_some() {
+                continue;
+            }
+            let mut members = Vec::new();
+            let mut queue = VecDeque::from([entry]);
+            while let Some(idx) = queue.pop_front() {
+                if owner[idx].is_some() {
+                    continue;
+                }
+                owner[idx] = Some(routines.len());
+                members.push(idx);
+                queue.extend(blocks[idx].successors.iter().filter(|edge| edge.kind != EdgeKind::Call).map(|edge| edge.target));
+            }
+            members.sort();
+            let entry_label = &asm_lines[blocks[entry].lines[0]].label;
+            let routine_name = if routines.is_empty() && !name.is_empty() { name.clone() } else { entry_label.clone() };
+            routines.push((routine_name, members));
+        }
+        Self { name, blocks, routines }
+    }
+
+    /// Writes the graph as Graphviz DOT with one cluster per routine. Calls are dashed, jumps are
+    /// labelled with the jump mnemonic.
+    pub fn to_dot(&self, global_map: &GlobalMap, asm_lines: &[ASMLine], lines: &[SourceLine]) -> String {
+        let name = if self.name.is_empty() { "program" } else { &self.name };
+        let mut dot = format!("digraph \"{}\" {{\n    node [shape=box, fontname=\"monospace\"];\n", escape(name));
+        let node = |idx: usize| {
+            let text = self.blocks[idx].lines.iter().map(|&line| {
+                let asm_line = &asm_lines[line];
+                let parts = split_line(global_map, &lines[asm_line.line_no - 1].text);
+                let source = format!("{} {} {}", parts.label, parts.opcode, parts.operand);
+                format!("{:06X}  {}\\l", asm_line.loc, escape(source.trim()))
+            }).collect::<String>();
+            format!("b{} [label=\"{}\"];", idx, text)
+        };
+        let mut clustered = vec![false; self.blocks.len()];
+        for (cluster, (routine, members)) in self.routines.iter().enumerate() {
+            dot += &format!("    subgraph cluster_{} {{\n        label=\"{}\";\n", cluster, escape(routine));
+            for &idx in members {
+                clustered[idx] = true;
+                dot += &format!("        {}\n", node(idx));
+            }
+            dot += "    }\n";
+        }
+        for idx in (0..self.blocks.len()).filter(|&idx| !clustered[idx]) {
+            dot += &format!("    {}\n", node(idx));
+        }
+        for (idx, block) in self.blocks.iter().enumerate() {
+            for edge in &block.successors {
+                let attributes = match edge.kind {
+                    EdgeKind::FallThrough => String::new(),
+                    EdgeKind::Jump => {
+                        let jump = mnemonic(&asm_lines[*block.lines.last().unwrap()]).unwrap_or_default();
+                        format!(" [label=\"{}\"]", jump)
+                    }
+                    EdgeKind::Call => String::from(" [style=dashed, label=\"call\"]"),
+                };
+                dot += &format!("    b{} -> b{}{};\n", idx, edge.target, attributes);
+            }
+        }
+        dot + "}\n"
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
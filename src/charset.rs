@@ -0,0 +1,72 @@
+//! Character sets that `C'...'` constants are encoded in. Parsing a constant picks up the character
+//! set in effect for the line, which the `--charset` option and the `CODEPAGE` directive choose.
+use std::cell::Cell;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Charset {
+    Ascii,
+    Ebcdic,
+}
+
+/// Code page 037 for the printable ASCII characters, from the space (0x20) to `~` (0x7E).
+const EBCDIC_PRINTABLE: [u8; 95] = [
+    0x40, 0x5A, 0x7F, 0x7B, 0x5B, 0x6C, 0x50, 0x7D, 0x4D, 0x5D, 0x5C, 0x4E, 0x6B, 0x60, 0x4B, 0x61,
+    0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0x7A, 0x5E, 0x4C, 0x7E, 0x6E, 0x6F,
+    0x7C, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xD1, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6,
+    0xD7, 0xD8, 0xD9, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xBA, 0xE0, 0xBB, 0xB0, 0x6D,
+    0x79, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96,
+    0x97, 0x98, 0x99, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8, 0xA9, 0xC0, 0x4F, 0xD0, 0xA1,
+];
+/// The control characters that have an escape, `\0`, `\t`, `\n` and `\r`, in code page 037.
+const EBCDIC_CONTROL: [(char, u8); 4] = [('\0', 0x00), ('\t', 0x05), ('\n', 0x25), ('\r', 0x0D)];
+
+impl Charset {
+    pub fn parse(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "ascii" => Charset::Ascii,
+            "ebcdic" => Charset::Ebcdic,
+            _ => panic!("Unknown character set {}, expected ascii or ebcdic", name)
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Charset::Ascii => "ascii",
+            Charset::Ebcdic => "ebcdic",
+        }
+    }
+
+    pub fn encode(&self, c: char) -> u8 {
+        match self {
+            Charset::Ascii if c.is_ascii() => c as u8,
+            Charset::Ascii => panic!("{} isn't an ASCII character", c),
+            Charset::Ebcdic => match c {
+                ' '..='~' => EBCDIC_PRINTABLE[c as usize - 0x20],
+                _ => EBCDIC_CONTROL.iter().find(|(control, _)| *control == c).map(|(_, byte)| *byte)
+                    .unwrap_or_else(|| panic!("{} has no EBCDIC code", c.escape_default())),
+            },
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<Charset> = const { Cell::new(Charset::Ascii) };
+}
+
+/// The character set constants parsed now are encoded in.
+pub fn current() -> Charset {
+    CURRENT.with(Cell::get)
+}
+
+/// Runs `f` with `charset` as the current character set, restoring the one before afterwards, even
+/// when `f` panics.
+pub fn with_charset<T>(charset: Charset, f: impl FnOnce() -> T) -> T {
+    struct Restore(Charset);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.0));
+        }
+    }
+    let _restore = Restore(CURRENT.with(|current| current.replace(charset)));
+    f()
+}
@@ -0,0 +1,112 @@
+use std::fmt::{Display, Formatter};
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic;
+use crate::utils::edit_distance;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line_no: usize,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line_no)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: SourceLocation,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, location: &SourceLocation, message: impl Into<String>) -> Self {
+        Self { severity, location: location.clone(), message: message.into() }
+    }
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}: {}", self.location, self.severity.as_str(), self.message)
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<Diagnostic>> = const { RefCell::new(None) };
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else {
+        String::from("unknown error")
+    }
+}
+
+/// `", did you mean X?"` for the candidate closest to `word`, or nothing when none is close enough
+/// to be a likely typo.
+pub fn did_you_mean<'a>(word: &str, candidates: impl IntoIterator<Item = &'a String>) -> String {
+    let word = word.to_uppercase();
+    let closest = candidates.into_iter()
+        .map(|candidate| (edit_distance(&word, &candidate.to_uppercase()), candidate))
+        .filter(|(distance, _)| *distance <= (word.len() / 3).max(1) && *distance < word.len())
+        .min();
+    match closest {
+        Some((_, candidate)) => format!(", did you mean {}?", candidate),
+        None => String::new(),
+    }
+}
+
+/// Runs `f`, prefixing any panic message raised inside it with `location`.
+pub fn at_location<T>(location: &SourceLocation, f: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = panic_message(payload.as_ref());
+            LAST_ERROR.with(|last_error| {
+                let mut last_error = last_error.borrow_mut();
+                //keep the innermost location when calls are nested
+                if last_error.is_none() {
+                    *last_error = Some(Diagnostic::new(Severity::Error, location, message.clone()));
+                }
+            });
+            panic!("{}: {}", location, message)
+        }
+    }
+}
+
+/// Runs `f` and turns a panic raised inside it into an error diagnostic, for callers that have to
+/// keep going after an error, like the language server. Panics raised outside `at_location` get `file`
+/// with no line.
+pub fn catch_diagnostic<T>(file: &str, f: impl FnOnce() -> T) -> Result<T, Diagnostic> {
+    LAST_ERROR.with(|last_error| last_error.borrow_mut().take());
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).map_err(|payload| {
+        LAST_ERROR.with(|last_error| last_error.borrow_mut().take()).unwrap_or_else(|| {
+            let location = SourceLocation { file: file.to_string(), line_no: 0 };
+            Diagnostic::new(Severity::Error, &location, panic_message(payload.as_ref()))
+        })
+    })
+}
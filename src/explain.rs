@@ -0,0 +1,79 @@
+use crate::global_map::GlobalMap;
+use crate::parse_utils::*;
+use crate::utils::i32_to_bin_string;
+
+/// Describes, step by step, how `get_object_code` arrived at the object code of an instruction.
+/// Directives aren't explained.
+pub fn explain(global_map: &GlobalMap, asm_line: &ASMLine, object_code: &ObjectCode) -> Option<Vec<String>> {
+    let OpcodeSpec::Opcode(mnemonic, format) = &asm_line.opcode_spec else {
+        return None;
+    };
+    let opcode = global_map.get_opcode_value(mnemonic).opcode;
+    let mut steps = vec![format!("opcode {} = {:02X} = {}", mnemonic, opcode, i32_to_bin_string(opcode as i32, 8))];
+    match format {
+        OpcodeFormat::One => {}
+        OpcodeFormat::Two => {
+            if let AddressSpec::FormatTwo(operand) = &asm_line.address_spec {
+                let byte = operand.as_byte();
+                let detail = match operand {
+                    FormatTwoOperand::RegisterCount(_, n) => format!(" (count stored as n-1 = {})", n),
+                    _ => String::new(),
+                };
+                steps.push(format!("r1 = {:X}, r2 = {:X}{}", byte >> 4, byte & 0xF, detail));
+            }
+        }
+        OpcodeFormat::Three | OpcodeFormat::Four => {
+            let nixbpe = object_code.nixbpe.expect("Format 3/4 object code without nixbpe");
+            steps.push(format!("top 6 bits of opcode = {}", i32_to_bin_string((opcode >> 2) as i32, 6)));
+            let addressing_mode = match &asm_line.address_spec {
+                AddressSpec::Address(_, mode) | AddressSpec::Label(_, mode) => *mode,
+                _ => AddressingModes::None,
+            };
+            let (ni_reason, x_reason) = match addressing_mode {
+                AddressingModes::Immediate => ("immediate operand (#)", "not indexed"),
+                AddressingModes::Indirect => ("indirect operand (@)", "not indexed"),
+                AddressingModes::Indexed if !nixbpe.n => ("SIC compatible indexed address", "operand ends with ,X"),
+                AddressingModes::Indexed => ("simple addressing", "operand ends with ,X"),
+                AddressingModes::None => ("no operand, simple addressing", "not indexed"),
+                AddressingModes::Direct => ("simple addressing", "not indexed"),
+            };
+            let (b_reason, p_reason) = match object_code.displacement {
+                Some(Displacement { relativity: Relativity::Pc, disp, .. }) => {
+                    ("PC-relative was chosen".to_string(), format!("displacement {} is within -2048..2047", disp))
+                }
+                Some(Displacement { relativity: Relativity::Base, disp, from }) => {
+                    let pc_disp = object_code.target.unwrap_or(0) as i32 - (asm_line.loc + 3) as i32;
+                    (format!("base-relative, displacement {} from B = {:06X} is within 0..4095", disp, from), format!("PC-relative displacement {} is outside -2048..2047", pc_disp))
+                }
+                None => ("no base-relative displacement".to_string(), "no PC-relative displacement".to_string()),
+            };
+            let e_reason = if nixbpe.e { "format 4 (+)" } else { "format 3" };
+            steps.push(format!("n = {}, i = {}: {}", nixbpe.n as i32, nixbpe.i as i32, ni_reason));
+            steps.push(format!("x = {}: {}", nixbpe.x as i32, x_reason));
+            steps.push(format!("b = {}: {}", nixbpe.b as i32, b_reason));
+            steps.push(format!("p = {}: {}", nixbpe.p as i32, p_reason));
+            steps.push(format!("e = {}: {}", nixbpe.e as i32, e_reason));
+            if let Some(target) = object_code.target {
+                steps.push(format!("target address = {:06X}", target));
+            }
+            let field_len = if *format == OpcodeFormat::Four { 20 } else { 12 };
+            match object_code.displacement {
+                Some(Displacement { relativity, from, disp }) => {
+                    let (name, register) = match relativity {
+                        Relativity::Pc => ("PC-relative", "PC"),
+                        Relativity::Base => ("base-relative", "B"),
+                    };
+                    steps.push(format!("{}: disp = target - {} = {:06X} - {:06X} = {}", name, register, object_code.target.unwrap_or(0), from, disp));
+                    if disp < 0 {
+                        steps.push(format!("negative displacement in two's complement: {} + 4096 = {} = {:03X}", disp, disp + 4096, disp + 4096));
+                    }
+                }
+                None if nixbpe.e => steps.push(format!("extended: the {} bit address field holds the target address", field_len)),
+                None => steps.push(format!("direct: the {} bit address field holds the operand value", field_len)),
+            }
+            steps.push(format!("{} {} + address field {}", i32_to_bin_string((opcode >> 2) as i32, 6), nixbpe.as_bin_string(), &object_code.code[3..]));
+        }
+    }
+    steps.push(format!("object code = {}", object_code.code));
+    Some(steps)
+}
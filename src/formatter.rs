@@ -0,0 +1,122 @@
+use crate::global_map::GlobalMap;
+use crate::parse_utils::{split_line, OpcodeFormat};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Case {
+    Upper,
+    Lower,
+    Keep,
+}
+
+impl Case {
+    pub fn parse(case: &str) -> Self {
+        match case {
+            "upper" => Case::Upper,
+            "lower" => Case::Lower,
+            "keep" => Case::Keep,
+            _ => panic!("Unknown case {}, expected upper, lower or keep", case)
+        }
+    }
+    fn apply(&self, word: &str) -> String {
+        match self {
+            Case::Upper => word.to_uppercase(),
+            Case::Lower => word.to_lowercase(),
+            Case::Keep => word.to_string(),
+        }
+    }
+}
+
+/// Column widths, each counting the space after the column.
+#[derive(Clone, Copy, Debug)]
+pub struct FormatOptions {
+    pub label_width: usize,
+    pub opcode_width: usize,
+    pub operand_width: usize,
+    pub case: Case,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self { label_width: 8, opcode_width: 8, operand_width: 16, case: Case::Upper }
+    }
+}
+
+/// Rewrites source into aligned label, mnemonic, operand and comment columns. Lines that are only a
+/// comment, and blank lines, are kept as they are apart from trailing whitespace.
+pub fn format_source(global_map: &GlobalMap, source: &str, options: &FormatOptions) -> String {
+    let mut formatted = String::new();
+    for line in source.lines() {
+        let parts = split_line(global_map, line);
+        if parts.is_empty() {
+            formatted += line.trim_end();
+            formatted += "\n";
+            continue;
+        }
+        let mnemonic = parts.opcode.trim_start_matches('+');
+        let opcode = parts.opcode.replace(mnemonic, &options.case.apply(mnemonic));
+        let operand = format_operand(global_map, &mnemonic.to_uppercase(), &parts.operand, options.case);
+        let mut columns = vec![(parts.label, options.label_width), (opcode, options.opcode_width), (operand, options.operand_width), (parts.comment, 0)];
+        while columns.last().is_some_and(|(column, _)| column.is_empty()) {
+            columns.pop();
+        }
+        let mut formatted_line = String::new();
+        let last = columns.len() - 1;
+        for (idx, (column, width)) in columns.into_iter().enumerate() {
+            formatted_line += &column;
+            if idx != last {
+                let padding = width.saturating_sub(column.chars().count()).max(1);
+                formatted_line += &" ".repeat(padding);
+            }
+        }
+        formatted += &formatted_line;
+        formatted += "\n";
+    }
+    formatted
+}
+
+/// Normalizes the case of registers: every operand of a format 2 instruction, and the X of an
+/// indexed operand. Labels and constants are left alone.
+fn format_operand(global_map: &GlobalMap, mnemonic: &str, operand: &str, case: Case) -> String {
+    let is_register = |word: &str| global_map.register_map.contains_key(&word.to_uppercase());
+    let is_format_two = global_map.opcode_map.get(mnemonic).is_some_and(|detail| detail.format == OpcodeFormat::Two);
+    if is_format_two {
+        return operand.split(',').map(|word| if is_register(word) { case.apply(word) } else { word.to_string() }).collect::<Vec<String>>().join(",");
+    }
+    match operand.rsplit_once(',') {
+        Some((address, index)) if index.eq_ignore_ascii_case("X") && !operand.contains('\'') => format!("{},{}", address, case.apply(index)),
+        _ => operand.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        format_source(&GlobalMap::init(), source, &FormatOptions::default())
+    }
+
+    #[test]
+    fn aligns_columns() {
+        assert_eq!(format("copy start 1000\n  . comment\n\nfirst stl retadr . save\n clear x\n rsub\n"),
+            "copy    START   1000\n  . comment\n\nfirst   STL     retadr          . save\n        CLEAR   X\n        RSUB\n");
+    }
+
+    #[test]
+    fn keeps_text_after_the_operand() {
+        assert_eq!(format("FIRST STL RETADR save return address\n LDA BUF extra . dotted\n"),
+            "FIRST   STL     RETADR          save return address\n        LDA     BUF             extra . dotted\n");
+    }
+
+    #[test]
+    fn joins_operand_lists_with_spaces() {
+        assert_eq!(format(" WORD 1, 2, 3\nDATA BYTE C'A, B' , X'01'\n LDA BUF, x\n"),
+            "        WORD    1,2,3\nDATA    BYTE    C'A, B',X'01'\n        LDA     BUF,X\n");
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        let formatted = format("FIRST STL RETADR save return address\n WORD 1, 2, 3\n");
+        assert_eq!(format(&formatted), formatted);
+    }
+}
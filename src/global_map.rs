@@ -1,44 +1,108 @@
-use std::collections::HashMap;
-use crate::parse_utils::OpcodeFormat;
-use crate::utils::i32_to_hex_string;
+use std::collections::{HashMap, HashSet};
+use crate::charset::Charset;
+use crate::diagnostics::{did_you_mean, SourceLocation};
+use crate::parse_utils::{Expression, OpcodeFormat, OperandShape};
 
 #[derive(Clone, Copy, Debug)]
 pub struct OpcodeDetail {
     pub opcode: u8,
     pub format: OpcodeFormat,
+    pub operands: OperandShape,
 }
 impl OpcodeDetail {
-    fn new(format: OpcodeFormat, opcode: u8) -> Self {
-        Self { opcode, format }
+    fn new(format: OpcodeFormat, opcode: u8, operands: OperandShape) -> Self {
+        Self { opcode, format, operands }
     }
 }
 
+/// A `C'...'` or `X'...'` constant. Character constants are kept as written, escapes included, along
+/// with the character set they are encoded in. Hex constants keep every byte written, leading zeroes
+/// included.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Constant {
-    SicString(String),
-    Hex(i32),
+    SicString(String, Charset),
+    Hex(Vec<u8>),
 }
 
 impl Constant {
     pub fn get_len(&self) -> usize {
+        self.bytes().len()
+    }
+    pub fn bytes(&self) -> Vec<u8> {
         match self {
-            Constant::SicString(string) => {
-                string.len()
-            }
-            Constant::Hex(val) => i32_to_hex_string(*val, 0).len().div_ceil(2)
+            Constant::SicString(string, charset) => unescape(string, *charset),
+            Constant::Hex(bytes) => bytes.clone(),
         }
     }
 }
 
 
-#[derive(Debug)]
+/// Whether `loop` and `LOOP` name the same label. Mnemonics, directives and registers are always
+/// matched without regard to case.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LabelCase {
+    Sensitive,
+    Insensitive,
+}
+
+impl LabelCase {
+    pub fn parse(case: &str) -> Self {
+        match case {
+            "sensitive" => LabelCase::Sensitive,
+            "insensitive" => LabelCase::Insensitive,
+            _ => panic!("Unknown label case {}, expected sensitive or insensitive", case)
+        }
+    }
+}
+
+/// The bytes of a character constant, with `\n`, `\t`, `\r`, `\0`, `\\`, `\'` and `\xNN` escapes.
+/// `\xNN` is the byte NN whatever the character set.
+fn unescape(string: &str, charset: Charset) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.push(charset.encode(c));
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('n') => charset.encode('\n'),
+            Some('t') => charset.encode('\t'),
+            Some('r') => charset.encode('\r'),
+            Some('0') => charset.encode('\0'),
+            Some('\\') => charset.encode('\\'),
+            Some('\'') => charset.encode('\''),
+            Some('x') => {
+                let digits = chars.by_ref().take(2).collect::<String>();
+                u8::from_str_radix(&digits, 16).ok().filter(|_| digits.len() == 2).unwrap_or_else(|| panic!("Invalid escape \\x{}, expected two hex digits", digits))
+            }
+            Some(c) => panic!("Invalid escape \\{}", c),
+            None => panic!("Character constant {} ends with a lone \\", string),
+        };
+        bytes.push(byte);
+    }
+    bytes
+}
+
+#[derive(Clone, Debug)]
 pub struct GlobalMap {
     pub opcode_map: HashMap<String, OpcodeDetail>,
     pub register_map: HashMap<String, i32>,
     pub label_map: HashMap<String, usize>,
+    //labels whose value doesn't move with the program, like `MAXLEN EQU 4096`; all others are relative
+    pub absolute_labels: HashSet<String>,
+    //the expression each EQU label was defined with, which is kept after the EQU is resolved
+    pub equ_expressions: HashMap<String, Expression>,
     pub constant_map: HashMap<String, Constant>,
     pub literal_pool: Vec<Constant>,
     pub literal_map: HashMap<Constant, usize>,
+    pub label_case: LabelCase,
+    //labels defined by IMPORT, with the line of the symbol file each one comes from
+    pub imported_labels: HashMap<String, SourceLocation>,
+    //where IMPORT looks for symbol files after the directory of the importing file
+    pub include_dirs: Vec<String>,
+    //the character set of constants in files without a CODEPAGE
+    pub charset: Charset,
 }
 
 impl GlobalMap {
@@ -47,23 +111,29 @@ impl GlobalMap {
             opcode_map: HashMap::new(),
             register_map: HashMap::new(),
             label_map: HashMap::new(),
+            absolute_labels: HashSet::new(),
+            equ_expressions: HashMap::new(),
             constant_map: HashMap::new(),
             literal_pool: Vec::new(),
             literal_map: HashMap::new(),
+            label_case: LabelCase::Sensitive,
+            imported_labels: HashMap::new(),
+            include_dirs: Vec::new(),
+            charset: Charset::Ascii,
         };
 
         let codes: Vec<(&'static str, OpcodeDetail)> = vec![
             Self::produce_map_member("ADD", OpcodeFormat::Three, 0x18),
             Self::produce_map_member("ADDF", OpcodeFormat::Three, 0x58),
-            Self::produce_map_member("ADDR", OpcodeFormat::Two, 0x90),
+            Self::produce_format_two_member("ADDR", OperandShape::RegisterPair, 0x90),
             Self::produce_map_member("AND", OpcodeFormat::Three, 0x40),
-            Self::produce_map_member("CLEAR", OpcodeFormat::Two, 0xB4),
+            Self::produce_format_two_member("CLEAR", OperandShape::Register, 0xB4),
             Self::produce_map_member("COMP", OpcodeFormat::Three, 0x28),
             Self::produce_map_member("COMPF", OpcodeFormat::Three, 0x88),
-            Self::produce_map_member("COMPR", OpcodeFormat::Two, 0xA0),
+            Self::produce_format_two_member("COMPR", OperandShape::RegisterPair, 0xA0),
             Self::produce_map_member("DIV", OpcodeFormat::Three, 0x24),
             Self::produce_map_member("DIVF", OpcodeFormat::Three, 0x64),
-            Self::produce_map_member("DIVR", OpcodeFormat::Two, 0x9C),
+            Self::produce_format_two_member("DIVR", OperandShape::RegisterPair, 0x9C),
             Self::produce_map_member("FIX", OpcodeFormat::One, 0xC4),
             Self::produce_map_member("FLOAT", OpcodeFormat::One, 0xC0),
             Self::produce_map_member("HIO", OpcodeFormat::One, 0xF4),
@@ -83,14 +153,14 @@ impl GlobalMap {
             Self::produce_map_member("LPS", OpcodeFormat::Three, 0xD0),
             Self::produce_map_member("MUL", OpcodeFormat::Three, 0x20),
             Self::produce_map_member("MULF", OpcodeFormat::Three, 0x60),
-            Self::produce_map_member("MULR", OpcodeFormat::Two, 0x98),
+            Self::produce_format_two_member("MULR", OperandShape::RegisterPair, 0x98),
             Self::produce_map_member("NORM", OpcodeFormat::One, 0xC8),
             Self::produce_map_member("OR", OpcodeFormat::Three, 0x44),
             Self::produce_map_member("RD", OpcodeFormat::Three, 0xD8),
-            Self::produce_map_member("RMO", OpcodeFormat::Two, 0xAC),
+            Self::produce_format_two_member("RMO", OperandShape::RegisterPair, 0xAC),
             Self::produce_map_member("RSUB", OpcodeFormat::Three, 0x4C),
-            Self::produce_map_member("SHIFTL", OpcodeFormat::Two, 0xA4),
-            Self::produce_map_member("SHIFTR", OpcodeFormat::Two, 0xA8),
+            Self::produce_format_two_member("SHIFTL", OperandShape::RegisterCount, 0xA4),
+            Self::produce_format_two_member("SHIFTR", OperandShape::RegisterCount, 0xA8),
             Self::produce_map_member("SIO", OpcodeFormat::One, 0xF0),
             Self::produce_map_member("SSK", OpcodeFormat::Three, 0xEC),
             Self::produce_map_member("STA", OpcodeFormat::Three, 0x0C),
@@ -105,12 +175,12 @@ impl GlobalMap {
             Self::produce_map_member("STX", OpcodeFormat::Three, 0x10),
             Self::produce_map_member("SUB", OpcodeFormat::Three, 0x1C),
             Self::produce_map_member("SUBF", OpcodeFormat::Three, 0x5C),
-            Self::produce_map_member("SUBR", OpcodeFormat::Two, 0x94),
-            Self::produce_map_member("SVC", OpcodeFormat::Two, 0xB0),
+            Self::produce_format_two_member("SUBR", OperandShape::RegisterPair, 0x94),
+            Self::produce_format_two_member("SVC", OperandShape::Count, 0xB0),
             Self::produce_map_member("TD", OpcodeFormat::Three, 0xE0),
             Self::produce_map_member("TIO", OpcodeFormat::One, 0xF8),
             Self::produce_map_member("TIX", OpcodeFormat::Three, 0x2C),
-            Self::produce_map_member("TIXR", OpcodeFormat::Two, 0xB8),
+            Self::produce_format_two_member("TIXR", OperandShape::Register, 0xB8),
             Self::produce_map_member("WD", OpcodeFormat::Three, 0xDC),
         ];
         codes.iter().for_each(|code| {
@@ -137,14 +207,164 @@ impl GlobalMap {
         format: OpcodeFormat,
         opcode: u8,
     ) -> (&'static str, OpcodeDetail) {
-        (mnemonic, OpcodeDetail::new(format, opcode))
+        let operands = match format {
+            OpcodeFormat::One => OperandShape::None,
+            _ => OperandShape::Memory,
+        };
+        (mnemonic, OpcodeDetail::new(format, opcode, operands))
+    }
+    fn produce_format_two_member(
+        mnemonic: &'static str,
+        operands: OperandShape,
+        opcode: u8,
+    ) -> (&'static str, OpcodeDetail) {
+        (mnemonic, OpcodeDetail::new(OpcodeFormat::Two, opcode, operands))
     }
     pub fn get_reg_value(&self, reg: impl Into<String>) -> i32 {
         let reg = reg.into();
-        *self.register_map.get(&reg).unwrap_or_else(|| panic!("Invalid register {}", reg))
+        *self.register_map.get(&reg.to_uppercase()).unwrap_or_else(|| panic!("Invalid register {}{}", reg, did_you_mean(&reg, self.register_map.keys())))
     }
     pub fn get_opcode_value(&self, opcode: impl Into<String>) -> OpcodeDetail {
         let opcode = opcode.into();
-        *self.opcode_map.get(&opcode).unwrap_or_else(|| panic!("Invalid opcode {}", opcode))
+        *self.opcode_map.get(&opcode.to_uppercase()).unwrap_or_else(|| panic!("Invalid opcode {}{}", opcode, did_you_mean(&opcode, self.opcode_map.keys())))
+    }
+    pub fn is_absolute(&self, label: &str) -> bool {
+        self.absolute_labels.contains(label)
+    }
+    pub fn get_label_value(&self, label: &str) -> usize {
+        *self.label_map.get(label).unwrap_or_else(|| panic!("Invalid label {}{}", label, did_you_mean(label, self.label_map.keys())))
+    }
+    pub fn is_register(&self, reg: &str) -> bool {
+        self.register_map.contains_key(&reg.to_uppercase())
+    }
+    pub fn is_mnemonic(&self, mnemonic: &str) -> bool {
+        self.opcode_map.contains_key(&mnemonic.to_uppercase())
+    }
+    /// The name a label is stored under in `label_map`.
+    pub fn fold_label(&self, label: &str) -> String {
+        match self.label_case {
+            LabelCase::Sensitive => label.to_string(),
+            LabelCase::Insensitive => label.to_uppercase(),
+        }
+    }
+}
+
+/// Short description of what an instruction does, in the notation of the SIC/XE instruction set table.
+pub fn get_opcode_description(mnemonic: &str) -> Option<&'static str> {
+    let description = match mnemonic {
+        "ADD" => "A <- (A) + (m..m+2)",
+        "ADDF" => "F <- (F) + (m..m+5)",
+        "ADDR" => "r2 <- (r2) + (r1)",
+        "AND" => "A <- (A) & (m..m+2)",
+        "CLEAR" => "r1 <- 0",
+        "COMP" => "(A) : (m..m+2)",
+        "COMPF" => "(F) : (m..m+5)",
+        "COMPR" => "(r1) : (r2)",
+        "DIV" => "A <- (A) / (m..m+2)",
+        "DIVF" => "F <- (F) / (m..m+5)",
+        "DIVR" => "r2 <- (r2) / (r1)",
+        "FIX" => "A <- (F) converted to integer",
+        "FLOAT" => "F <- (A) converted to floating point",
+        "HIO" => "Halt I/O channel number (A)",
+        "J" => "PC <- m",
+        "JEQ" => "PC <- m if CC set to =",
+        "JGT" => "PC <- m if CC set to >",
+        "JLT" => "PC <- m if CC set to <",
+        "JSUB" => "L <- (PC); PC <- m",
+        "LDA" => "A <- (m..m+2)",
+        "LDB" => "B <- (m..m+2)",
+        "LDCH" => "A [rightmost byte] <- (m)",
+        "LDF" => "F <- (m..m+5)",
+        "LDL" => "L <- (m..m+2)",
+        "LDS" => "S <- (m..m+2)",
+        "LDT" => "T <- (m..m+2)",
+        "LDX" => "X <- (m..m+2)",
+        "LPS" => "Load processor status from information beginning at address m",
+        "MUL" => "A <- (A) * (m..m+2)",
+        "MULF" => "F <- (F) * (m..m+5)",
+        "MULR" => "r2 <- (r2) * (r1)",
+        "NORM" => "F <- (F) normalized",
+        "OR" => "A <- (A) | (m..m+2)",
+        "RD" => "A [rightmost byte] <- data from device specified by (m)",
+        "RMO" => "r2 <- (r1)",
+        "RSUB" => "PC <- (L)",
+        "SHIFTL" => "r1 <- (r1) left circular shift n bits",
+        "SHIFTR" => "r1 <- (r1) right shift n bits, vacated bits set to the leftmost bit",
+        "SIO" => "Start I/O channel number (A), address of channel program is given by (S)",
+        "SSK" => "Protection key for address m <- (A)",
+        "STA" => "m..m+2 <- (A)",
+        "STB" => "m..m+2 <- (B)",
+        "STCH" => "m <- (A) [rightmost byte]",
+        "STF" => "m..m+5 <- (F)",
+        "STI" => "Interval timer value <- (m..m+2)",
+        "STL" => "m..m+2 <- (L)",
+        "STS" => "m..m+2 <- (S)",
+        "STSW" => "m..m+2 <- (SW)",
+        "STT" => "m..m+2 <- (T)",
+        "STX" => "m..m+2 <- (X)",
+        "SUB" => "A <- (A) - (m..m+2)",
+        "SUBF" => "F <- (F) - (m..m+5)",
+        "SUBR" => "r2 <- (r2) - (r1)",
+        "SVC" => "Generate SVC interrupt n",
+        "TD" => "Test device specified by (m)",
+        "TIO" => "Test I/O channel number (A)",
+        "TIX" => "X <- (X) + 1; (X) : (m..m+2)",
+        "TIXR" => "X <- (X) + 1; (X) : (r1)",
+        "WD" => "Device specified by (m) <- (A) [rightmost byte]",
+        _ => return None,
+    };
+    Some(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(string: &str, charset: Charset) -> Vec<u8> {
+        Constant::SicString(string.to_string(), charset).bytes()
+    }
+
+    #[test]
+    fn escapes_follow_the_charset() {
+        assert_eq!(bytes("A\\n\\t\\r\\0", Charset::Ascii), vec![0x41, 0x0A, 0x09, 0x0D, 0x00]);
+        assert_eq!(bytes("A\\n\\t\\r\\0", Charset::Ebcdic), vec![0xC1, 0x25, 0x05, 0x0D, 0x00]);
+        assert_eq!(bytes("\\\\\\'", Charset::Ascii), vec![b'\\', b'\'']);
+        assert_eq!(bytes("\\\\\\'", Charset::Ebcdic), vec![0xE0, 0x7D]);
+    }
+
+    #[test]
+    fn hex_escapes_are_raw_bytes() {
+        assert_eq!(bytes("\\x00\\x7f\\xFF", Charset::Ascii), vec![0x00, 0x7F, 0xFF]);
+        assert_eq!(bytes("\\x41B", Charset::Ebcdic), vec![0x41, 0xC2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid escape \\q")]
+    fn rejects_unknown_escapes() {
+        bytes("A\\qB", Charset::Ascii);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid escape \\x4, expected two hex digits")]
+    fn rejects_short_hex_escapes() {
+        bytes("\\x4", Charset::Ascii);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid escape \\xG1, expected two hex digits")]
+    fn rejects_non_hex_escapes() {
+        bytes("\\xG1", Charset::Ascii);
+    }
+
+    #[test]
+    #[should_panic(expected = "ends with a lone \\")]
+    fn rejects_trailing_backslashes() {
+        bytes("AB\\", Charset::Ascii);
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't an ASCII character")]
+    fn rejects_non_ascii_characters() {
+        bytes("é", Charset::Ascii);
     }
 }
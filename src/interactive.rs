@@ -1,4 +1,7 @@
-pub fn interactive_mode()->Vec<String> {
+use crate::batch::{expand_includes, SourceLine};
+use crate::diagnostics::SourceLocation;
+
+pub fn interactive_mode(include_dirs: &[String]) -> Vec<SourceLine> {
     print_help();
     let mut lines = Vec::new();
     loop {
@@ -18,7 +21,11 @@ pub fn interactive_mode()->Vec<String> {
         }
         lines.push(input.to_string());
     }
-    lines 
+    let lines = lines.into_iter().enumerate().map(|(idx, text)| SourceLine {
+        text,
+        location: SourceLocation { file: String::from("<stdin>"), line_no: idx + 1 },
+    }).collect();
+    expand_includes(lines, include_dirs, &mut Vec::new())
 }
 fn print_help() {
     println!("INTERACTIVE MODE:");
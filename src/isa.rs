@@ -0,0 +1,79 @@
+//! Changes to the instruction set, made with `OPDEF` in the source or listed in a `--isa` file. Both
+//! take `NAME,opcode,format[,shape]` to add or override a mnemonic and `NAME,REMOVE` to remove one.
+//! An ISA file holds one of these per line; blank lines and lines starting with `.` are skipped.
+//! Only the encoding of an instruction is described. There is no simulator to run it yet, so what a
+//! new opcode does can't be given.
+use std::fs;
+use crate::diagnostics::{at_location, SourceLocation};
+use crate::global_map::{GlobalMap, OpcodeDetail};
+use crate::parse_utils::*;
+
+/// The mnemonic and what it becomes, None when it is removed.
+pub fn parse_opdef(operand: &str) -> (String, Option<OpcodeDetail>) {
+    let fields = operand.split(',').map(str::trim).collect::<Vec<&str>>();
+    let mnemonic = fields[0].to_uppercase();
+    if mnemonic.is_empty() || !mnemonic.chars().all(|c| c.is_ascii_alphanumeric()) {
+        panic!("Invalid mnemonic {}", fields[0])
+    }
+    if is_directive(&mnemonic) {
+        panic!("{} is a directive and can't be redefined", mnemonic)
+    }
+    let (opcode, format, shape) = match fields[1..] {
+        [remove] if remove.eq_ignore_ascii_case("REMOVE") => return (mnemonic, None),
+        [opcode, format] => (opcode, format, None),
+        [opcode, format, shape] => (opcode, format, Some(OperandShape::parse(shape))),
+        _ => panic!("OPDEF takes NAME,opcode,format[,shape] or NAME,REMOVE, not {}", operand),
+    };
+    let opcode = parse_number(opcode).and_then(|opcode| u8::try_from(opcode).ok())
+        .unwrap_or_else(|| panic!("Invalid opcode {} for {}, expected a byte", opcode, mnemonic));
+    let format = match format {
+        "1" => OpcodeFormat::One,
+        "2" => OpcodeFormat::Two,
+        //format 3 instructions can always be written as format 4 with +
+        "3" => OpcodeFormat::Three,
+        _ => panic!("Invalid format {} for {}, expected 1, 2 or 3", format, mnemonic),
+    };
+    let operands = match (format, shape) {
+        (OpcodeFormat::One, None | Some(OperandShape::None)) => OperandShape::None,
+        (OpcodeFormat::Three, None) => OperandShape::Memory,
+        (OpcodeFormat::Three, Some(shape @ (OperandShape::None | OperandShape::Memory))) => shape,
+        (OpcodeFormat::Two, Some(shape @ (OperandShape::Register | OperandShape::RegisterPair | OperandShape::RegisterCount | OperandShape::Count))) => shape,
+        (OpcodeFormat::Two, None) => panic!("{} is format 2, so it needs a shape: register, register-pair, register-count or count", mnemonic),
+        (_, shape) => panic!("Operand shape {:?} doesn't go with format {} for {}", shape.unwrap_or(OperandShape::None), fields[2], mnemonic),
+    };
+    //n and i take the low two bits of the first byte of formats 3 and 4
+    if format == OpcodeFormat::Three && opcode & 0b11 != 0 {
+        panic!("Opcode {:02X} of {} has to be a multiple of 4, since it is format 3", opcode, mnemonic)
+    }
+    (mnemonic, Some(OpcodeDetail { opcode, format, operands }))
+}
+
+/// Adds, overrides or removes the mnemonic, as parse_opdef returns it.
+pub fn define_opcode(global_map: &mut GlobalMap, mnemonic: String, detail: Option<OpcodeDetail>) {
+    match (mnemonic, detail) {
+        (mnemonic, Some(detail)) => {
+            global_map.opcode_map.insert(mnemonic, detail);
+        }
+        (mnemonic, None) => {
+            if global_map.opcode_map.remove(&mnemonic).is_none() {
+                panic!("Can't remove {}, it isn't a mnemonic", mnemonic)
+            }
+        }
+    }
+}
+
+/// Applies every line of an ISA file to the instruction set.
+pub fn load_isa(global_map: &mut GlobalMap, filename: &str) {
+    let text = fs::read_to_string(filename).unwrap_or_else(|_| panic!("Unable to open ISA file {}", filename));
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('.') {
+            continue;
+        }
+        let location = SourceLocation { file: filename.to_string(), line_no: idx + 1 };
+        at_location(&location, || {
+            let (mnemonic, detail) = parse_opdef(line);
+            define_opcode(global_map, mnemonic, detail)
+        });
+    }
+}
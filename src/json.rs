@@ -0,0 +1,212 @@
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+    pub fn string(value: impl Into<String>) -> Self {
+        Json::String(value.into())
+    }
+    pub fn number(value: impl TryInto<i64>) -> Self {
+        Json::Number(value.try_into().unwrap_or_else(|_| panic!("Number doesn't fit in JSON")))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(field, _)| field == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { chars: text.chars().collect(), pos: 0 };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.chars.len() {
+            return Err(format!("Unexpected {} at {}", parser.chars[parser.pos], parser.pos));
+        }
+        Ok(value)
+    }
+
+    /// Writes the value on one line, escaping strings as RFC 8259 requires.
+    pub fn to_json_string(&self) -> String {
+        match self {
+            Json::Null => String::from("null"),
+            Json::Bool(value) => value.to_string(),
+            Json::Number(value) => value.to_string(),
+            Json::String(value) => escape(value),
+            Json::Array(values) => {
+                format!("[{}]", values.iter().map(Json::to_json_string).collect::<Vec<String>>().join(","))
+            }
+            Json::Object(fields) => {
+                format!("{{{}}}", fields.iter().map(|(key, value)| format!("{}:{}", escape(key), value.to_json_string())).collect::<Vec<String>>().join(","))
+            }
+        }
+    }
+}
+
+fn escape(string: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            c if (c as u32) < 0x20 => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped + "\""
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+    fn next(&mut self) -> Result<char, String> {
+        let c = *self.chars.get(self.pos).ok_or_else(|| String::from("Unexpected end of JSON"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        for c in expected.chars() {
+            if self.next()? != c {
+                return Err(format!("Expected {} at {}", expected, self.pos - 1));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.pos) {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.parse_string().map(Json::String),
+            Some('[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.parse_value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        ']' => return Ok(Json::Array(values)),
+                        c => return Err(format!("Unexpected {} in array at {}", c, self.pos - 1)),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.parse_string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    fields.push((key, self.parse_value()?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        '}' => return Ok(Json::Object(fields)),
+                        c => return Err(format!("Unexpected {} in object at {}", c, self.pos - 1)),
+                    }
+                }
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|c| *c == '-' || *c == '+' || *c == '.' || *c == 'e' || *c == 'E' || c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                let number = self.chars[start..self.pos].iter().collect::<String>();
+                //fractions aren't used by anything reading JSON here, so they are truncated
+                number.parse::<i64>().or_else(|_| number.parse::<f64>().map(|value| value as i64))
+                    .map(Json::Number)
+                    .map_err(|_| format!("Invalid number {}", number))
+            }
+            Some(c) => Err(format!("Unexpected {} at {}", c, self.pos)),
+            None => Err(String::from("Unexpected end of JSON")),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut string = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(string),
+                '\\' => match self.next()? {
+                    '"' => string.push('"'),
+                    '\\' => string.push('\\'),
+                    '/' => string.push('/'),
+                    'b' => string.push('\u{8}'),
+                    'f' => string.push('\u{c}'),
+                    'n' => string.push('\n'),
+                    'r' => string.push('\r'),
+                    't' => string.push('\t'),
+                    'u' => {
+                        let mut code = self.parse_hex_escape()?;
+                        //surrogate pairs come as two escapes
+                        if (0xD800..0xDC00).contains(&code) {
+                            self.expect("\\u")?;
+                            let low = self.parse_hex_escape()?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                        }
+                        string.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    c => return Err(format!("Invalid escape \\{}", c)),
+                },
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn parse_hex_escape(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let c = self.next()?;
+            code = code * 16 + c.to_digit(16).ok_or_else(|| format!("Invalid unicode escape digit {}", c))?;
+        }
+        Ok(code)
+    }
+}
@@ -0,0 +1,224 @@
+//! `--format json` output. The document is a single object, and fields are only ever added to it,
+//! never renamed or removed, without bumping `schema_version`.
+//!
+//! ```text
+//! {
+//!   "schema_version": 1,
+//!   "program": { "name": string, "start": number, "length": number }|null,   // null when pass 1 failed
+//!   "lines": [{                              // comment and blank lines are left out
+//!     "file": string, "line": number,        // where the line was written, after INCLUDE
+//!     "text": string, "label": string|null,
+//!     "loc": number,                         // LOCCTR of the line
+//!     "opcode": { "kind": "directive", "name": string }
+//!             | { "kind": "instruction", "mnemonic": string, "format": 1|2|3|4 },
+//!     "operand": null
+//!              | { "kind": "address", "value": number }
+//!              | { "kind": "label", "name": string }
+//!              | { "kind": "literal" | "constant", "constant": constant }
+//!              | { "kind": "registers", "r1": number, "r2": number }
+//!              | { "kind": "words", "values": [number] }   // WORD values, signed
+//!              | { "kind": "bytes", "values": [number] }   // BYTE lists and FILL
+//!              | { "kind": "expression", "text": string }   // an EQU that couldn't be resolved
+//!              | { "kind": "condition", "text": string, "message": string|null }   // ASSERT
+//!              | { "kind": "message", "text": string },    // ERROR and WARNING
+//!     "addressing": "none"|"immediate"|"indirect"|"indexed"|"direct"|null,
+//!     "nixbpe": { "n": bool, "i": bool, "x": bool, "b": bool, "p": bool, "e": bool }|null,
+//!     "object_code": string|null             // upper case hex
+//!   }],
+//!   "symbols": [{ "name": string, "address": number, "kind": "relative"|"absolute" }],   // sorted by name
+//!   "literals": [{ "constant": constant, "address": number }],
+//!   "diagnostics": [{ "severity": "note"|"warning"|"error", "file": string, "line": number, "message": string }]
+//! }
+//! constant = { "type": "char", "value": string, "charset": "ascii"|"ebcdic" }   // value as written, escapes included
+//!          | { "type": "hex", "value": string }
+//! ```
+use crate::batch::SourceLine;
+use crate::diagnostics::Diagnostic;
+use crate::global_map::{Constant, GlobalMap};
+use crate::json::Json;
+use crate::object_program::ObjectProgram;
+use crate::parse_utils::*;
+
+pub const SCHEMA_VERSION: i64 = 1;
+
+pub fn json_report(global_map: &GlobalMap, lines: &[SourceLine], asm_lines: &[ASMLine], object_codes: &[Option<ObjectCode>], diagnostics: &[Diagnostic]) -> Json {
+    //nothing is assembled when pass 1 stops at an error
+    let program = (!asm_lines.is_empty()).then(|| ObjectProgram::from_assembly(global_map, asm_lines, object_codes));
+    let json_lines = asm_lines.iter().enumerate().map(|(idx, asm_line)| {
+        let object_code = object_codes.get(idx).cloned().flatten();
+        line_json(&lines[asm_line.line_no - 1], asm_line, object_code.as_ref())
+    }).collect();
+    let mut symbols = global_map.label_map.iter().collect::<Vec<(&String, &usize)>>();
+    symbols.sort();
+    let mut literals = global_map.literal_map.iter().collect::<Vec<(&Constant, &usize)>>();
+    literals.sort_by_key(|literal| *literal.1);
+    Json::object(vec![
+        ("schema_version", Json::Number(SCHEMA_VERSION)),
+        ("program", program.map_or(Json::Null, |program| Json::object(vec![
+            ("name", Json::string(program.header.name)),
+            ("start", Json::number(program.header.start)),
+            ("length", Json::number(program.header.length)),
+        ]))),
+        ("lines", Json::Array(json_lines)),
+        ("symbols", Json::Array(symbols.into_iter().map(|(name, address)| Json::object(vec![
+            ("name", Json::string(name)),
+            ("address", Json::number(*address)),
+            ("kind", Json::string(if global_map.is_absolute(name) { "absolute" } else { "relative" })),
+        ])).collect())),
+        ("literals", Json::Array(literals.into_iter().map(|(constant, address)| Json::object(vec![
+            ("constant", constant_json(constant)),
+            ("address", Json::number(*address)),
+        ])).collect())),
+        ("diagnostics", Json::Array(diagnostics.iter().map(diagnostic_json).collect())),
+    ])
+}
+
+fn line_json(source_line: &SourceLine, asm_line: &ASMLine, object_code: Option<&ObjectCode>) -> Json {
+    let opcode = match &asm_line.opcode_spec {
+        OpcodeSpec::Directive(name) => Json::object(vec![
+            ("kind", Json::string("directive")),
+            ("name", Json::string(name)),
+        ]),
+        OpcodeSpec::Opcode(mnemonic, format) => Json::object(vec![
+            ("kind", Json::string("instruction")),
+            ("mnemonic", Json::string(mnemonic)),
+            ("format", Json::Number(match format {
+                OpcodeFormat::One => 1,
+                OpcodeFormat::Two => 2,
+                OpcodeFormat::Three => 3,
+                OpcodeFormat::Four => 4,
+            })),
+        ]),
+    };
+    let has_operand = !matches!(asm_line.address_spec, AddressSpec::Address(_, AddressingModes::None));
+    let (operand, addressing) = match &asm_line.address_spec {
+        _ if !has_operand => (Json::Null, Json::Null),
+        AddressSpec::Address(address, mode) => (Json::object(vec![
+            ("kind", Json::string("address")),
+            ("value", Json::number(*address)),
+        ]), addressing_json(*mode)),
+        AddressSpec::Label(label, mode) => (Json::object(vec![
+            ("kind", Json::string("label")),
+            ("name", Json::string(label)),
+        ]), addressing_json(*mode)),
+        AddressSpec::Literal(constant) => (Json::object(vec![
+            ("kind", Json::string("literal")),
+            ("constant", constant_json(constant)),
+        ]), Json::Null),
+        AddressSpec::Constant(constant) => (Json::object(vec![
+            ("kind", Json::string("constant")),
+            ("constant", constant_json(constant)),
+        ]), Json::Null),
+        AddressSpec::FormatTwo(operand) => (Json::object(vec![
+            ("kind", Json::string("registers")),
+            ("r1", Json::number(operand.as_byte() >> 4)),
+            ("r2", Json::number(operand.as_byte() & 0xF)),
+        ]), Json::Null),
+        AddressSpec::Expression(expression) => (Json::object(vec![
+            ("kind", Json::string("expression")),
+            ("text", Json::string(expression.to_string())),
+        ]), Json::Null),
+        AddressSpec::Condition(condition, message) => (Json::object(vec![
+            ("kind", Json::string("condition")),
+            ("text", Json::string(condition.to_string())),
+            ("message", message.as_ref().map_or(Json::Null, |message| Json::string(message.clone()))),
+        ]), Json::Null),
+        AddressSpec::Message(message) => (Json::object(vec![
+            ("kind", Json::string("message")),
+            ("text", Json::string(message.clone())),
+        ]), Json::Null),
+        AddressSpec::Bytes(bytes) => (Json::object(vec![
+            ("kind", Json::string("bytes")),
+            ("values", Json::Array(bytes.iter().map(|byte| Json::number(*byte)).collect())),
+        ]), Json::Null),
+        AddressSpec::Words(words) => (Json::object(vec![
+            ("kind", Json::string("words")),
+            ("values", Json::Array(words.iter().map(|word| Json::number(*word)).collect())),
+        ]), Json::Null),
+    };
+    let nixbpe = match object_code.and_then(|object_code| object_code.nixbpe) {
+        Some(nixbpe) => Json::object(vec![
+            ("n", Json::Bool(nixbpe.n)),
+            ("i", Json::Bool(nixbpe.i)),
+            ("x", Json::Bool(nixbpe.x)),
+            ("b", Json::Bool(nixbpe.b)),
+            ("p", Json::Bool(nixbpe.p)),
+            ("e", Json::Bool(nixbpe.e)),
+        ]),
+        None => Json::Null,
+    };
+    Json::object(vec![
+        ("file", Json::string(&source_line.location.file)),
+        ("line", Json::number(source_line.location.line_no)),
+        ("text", Json::string(&source_line.text)),
+        ("label", if asm_line.label.is_empty() { Json::Null } else { Json::string(&asm_line.label) }),
+        ("loc", Json::number(asm_line.loc)),
+        ("opcode", opcode),
+        ("operand", operand),
+        ("addressing", addressing),
+        ("nixbpe", nixbpe),
+        ("object_code", object_code.map_or(Json::Null, |object_code| Json::string(&object_code.code))),
+    ])
+}
+
+fn addressing_json(mode: AddressingModes) -> Json {
+    Json::string(match mode {
+        AddressingModes::None => "none",
+        AddressingModes::Immediate => "immediate",
+        AddressingModes::Indirect => "indirect",
+        AddressingModes::Indexed => "indexed",
+        AddressingModes::Direct => "direct",
+    })
+}
+
+fn constant_json(constant: &Constant) -> Json {
+    match constant {
+        Constant::SicString(string, charset) => Json::object(vec![
+            ("type", Json::string("char")),
+            ("value", Json::string(string)),
+            ("charset", Json::string(charset.name())),
+        ]),
+        Constant::Hex(bytes) => Json::object(vec![
+            ("type", Json::string("hex")),
+            ("value", Json::string(bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<String>())),
+        ]),
+    }
+}
+
+fn diagnostic_json(diagnostic: &Diagnostic) -> Json {
+    Json::object(vec![
+        ("severity", Json::string(diagnostic.severity.as_str())),
+        ("file", Json::string(&diagnostic.location.file)),
+        ("line", Json::number(diagnostic.location.line_no)),
+        ("message", Json::string(&diagnostic.message)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+    use crate::assembler::{check_symbols, pass_one};
+    use crate::diagnostics::SourceLocation;
+
+    #[test]
+    fn reports_an_undefined_end_label() {
+        let lines = ["PROG START 1000", "FIRST LDA #1", " END FOO"].iter().enumerate().map(|(idx, text)| SourceLine {
+            text: text.to_string(),
+            location: SourceLocation { file: String::from("test.sic"), line_no: idx + 1 },
+        }).collect::<Vec<SourceLine>>();
+        let mut global_map = GlobalMap::init();
+        let asm_lines = pass_one(&mut global_map, &lines, &HashSet::new());
+        let diagnostics = check_symbols(&global_map, &asm_lines);
+        let report = json_report(&global_map, &lines, &asm_lines, &[], &diagnostics);
+        let program = report.get("program").unwrap();
+        assert_eq!(program.get("name").and_then(Json::as_str), Some("PROG"));
+        let Some(Json::Array(diagnostics)) = report.get("diagnostics") else {
+            panic!("Expected a diagnostics array")
+        };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].get("severity").and_then(Json::as_str), Some("error"));
+        assert_eq!(diagnostics[0].get("line").and_then(Json::as_i64), Some(3));
+        assert!(diagnostics[0].get("message").and_then(Json::as_str).unwrap().starts_with("undefined label FOO"));
+    }
+}
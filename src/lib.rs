@@ -0,0 +1,22 @@
+pub mod utils;
+pub mod global_map;
+pub mod nixbpe;
+pub mod parse_utils;
+pub mod interactive;
+pub mod batch;
+pub mod assembler;
+pub mod options;
+pub mod diagnostics;
+pub mod object_program;
+pub mod output;
+pub mod json;
+pub mod json_report;
+pub mod explain;
+pub mod lsp;
+pub mod formatter;
+pub mod lint;
+pub mod cfg;
+pub mod listing;
+pub mod symbols;
+pub mod charset;
+pub mod isa;
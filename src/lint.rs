@@ -0,0 +1,152 @@
+use std::collections::{HashMap, HashSet};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::global_map::GlobalMap;
+use crate::parse_utils::*;
+
+/// Size of SIC/XE memory in bytes.
+pub const MEMORY_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+    FallThroughData,
+    UnreachableCode,
+    UnusedLabel,
+    ImmediateLabel,
+    UnsavedLink,
+    WriteToCode,
+    MemoryOverflow,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 7] = [
+        Lint::FallThroughData,
+        Lint::UnreachableCode,
+        Lint::UnusedLabel,
+        Lint::ImmediateLabel,
+        Lint::UnsavedLink,
+        Lint::WriteToCode,
+        Lint::MemoryOverflow,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::FallThroughData => "fall-through-data",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::UnusedLabel => "unused-label",
+            Lint::ImmediateLabel => "immediate-label",
+            Lint::UnsavedLink => "unsaved-link",
+            Lint::WriteToCode => "write-to-code",
+            Lint::MemoryOverflow => "memory-overflow",
+        }
+    }
+    pub fn parse(name: &str) -> Self {
+        *Self::ALL.iter().find(|lint| lint.name() == name).unwrap_or_else(|| {
+            let names = Self::ALL.iter().map(Lint::name).collect::<Vec<&str>>();
+            panic!("Unknown lint {}, expected one of {}", name, names.join(", "))
+        })
+    }
+}
+
+fn is_data(line: &ASMLine) -> bool {
+    matches!(&line.opcode_spec, OpcodeSpec::Directive(directive) if matches!(directive.as_str(), "RESW" | "RESB" | "WORD" | "BYTE"))
+}
+
+fn mnemonic(line: &ASMLine) -> Option<&str> {
+    match &line.opcode_spec {
+        OpcodeSpec::Opcode(mnemonic, _) => Some(mnemonic),
+        OpcodeSpec::Directive(_) => None,
+    }
+}
+
+/// Every label the operand of the line uses, including those in EQU expressions and ASSERT conditions.
+fn used_labels<'a>(global_map: &'a GlobalMap, line: &'a ASMLine) -> Vec<&'a str> {
+    match &line.address_spec {
+        AddressSpec::Label(label, _) => vec![label],
+        AddressSpec::Expression(expression) => expression.labels().map(String::as_str).collect(),
+        AddressSpec::Condition(condition, _) => condition.labels().map(String::as_str).collect(),
+        _ => match global_map.equ_expressions.get(&line.label) {
+            Some(expression) if line.opcode_spec == OpcodeSpec::Directive(String::from("EQU")) => expression.labels().map(String::as_str).collect(),
+            _ => Vec::new(),
+        },
+    }
+}
+
+fn operand_label(line: &ASMLine) -> Option<&str> {
+    match &line.address_spec {
+        AddressSpec::Label(label, _) => Some(label),
+        _ => None,
+    }
+}
+
+/// Runs the enabled lints over pass 1 output and returns a warning for each finding.
+pub fn lint(global_map: &GlobalMap, asm_lines: &[ASMLine], enabled: &HashSet<Lint>) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    let mut warn = |lint: Lint, line: &ASMLine, message: String| {
+        if enabled.contains(&lint) {
+            warnings.push(Diagnostic::new(Severity::Warning, &line.location, format!("{} [{}]", message, lint.name())));
+        }
+    };
+    let definitions = asm_lines.iter().filter(|line| !line.label.is_empty()).map(|line| (line.label.as_str(), line)).collect::<HashMap<&str, &ASMLine>>();
+    let referenced = asm_lines.iter().flat_map(|line| used_labels(global_map, line)).collect::<HashSet<&str>>();
+
+    for (previous, line) in asm_lines.iter().zip(asm_lines.iter().skip(1)) {
+        let Some(previous_mnemonic) = mnemonic(previous) else {
+            continue;
+        };
+        let ends_flow = previous_mnemonic == "J" || previous_mnemonic == "RSUB";
+        if !ends_flow && is_data(line) {
+            warn(Lint::FallThroughData, line, format!("execution falls through from {} into data", previous_mnemonic));
+        }
+        if ends_flow && line.label.is_empty() && mnemonic(line).is_some() {
+            warn(Lint::UnreachableCode, line, format!("unreachable code after {}", previous_mnemonic));
+        }
+    }
+
+    for line in asm_lines.iter().skip(1) {
+        if !line.label.is_empty() && !referenced.contains(line.label.as_str()) {
+            warn(Lint::UnusedLabel, line, format!("label {} is never referenced", line.label));
+        }
+        let mnemonic = mnemonic(line);
+        if let AddressSpec::Label(label, AddressingModes::Immediate) = &line.address_spec {
+            let is_arithmetic = matches!(mnemonic, Some("ADD" | "SUB" | "MUL" | "DIV" | "COMP" | "AND" | "OR" | "TIX"));
+            if is_arithmetic && definitions.get(label.as_str()).is_some_and(|definition| is_data(definition)) {
+                warn(Lint::ImmediateLabel, line, format!("#{} uses the address of {} as the value, did you mean {}", label, label, label));
+            }
+        }
+        if mnemonic.is_some_and(|mnemonic| mnemonic.starts_with("ST") && mnemonic != "STI") {
+            if let Some(target) = operand_label(line).and_then(|label| definitions.get(label)) {
+                if self::mnemonic(target).is_some() {
+                    warn(Lint::WriteToCode, line, format!("{} writes into the instruction at {}", mnemonic.unwrap(), target.label));
+                }
+            }
+        }
+        if let (OpcodeSpec::Directive(directive), AddressSpec::Address(_, _)) = (&line.opcode_spec, &line.address_spec) {
+            if directive == "RESW" || directive == "RESB" {
+                let size = get_loc_inc(&line.opcode_spec, &line.address_spec);
+                if line.loc + size > MEMORY_SIZE {
+                    warn(Lint::MemoryOverflow, line, format!("{} of {} bytes at {:06X} doesn't fit in the {} bytes of memory", directive, size, line.loc, MEMORY_SIZE));
+                }
+            }
+        }
+    }
+
+    //a subroutine is what a JSUB jumps to, up to its first RSUB
+    let subroutines = asm_lines.iter().filter(|line| mnemonic(line) == Some("JSUB")).filter_map(operand_label).collect::<HashSet<&str>>();
+    for (start, line) in asm_lines.iter().enumerate() {
+        if !subroutines.contains(line.label.as_str()) {
+            continue;
+        }
+        for body_line in &asm_lines[start..] {
+            match mnemonic(body_line) {
+                Some("STL") => break,
+                Some("RMO") if matches!(body_line.address_spec, AddressSpec::FormatTwo(FormatTwoOperand::RegisterPair(r1, _)) if r1 as i32 == global_map.get_reg_value("L")) => break,
+                Some("RSUB") => break,
+                Some("JSUB") => {
+                    warn(Lint::UnsavedLink, body_line, format!("JSUB inside subroutine {} overwrites L before it is saved", line.label));
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+    warnings
+}
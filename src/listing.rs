@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use crate::batch::SourceLine;
+use crate::global_map::GlobalMap;
+use crate::parse_utils::*;
+
+pub const LISTING_DIRECTIVES: [&str; 6] = ["TITLE", "SUBTTL", "EJECT", "SPACE", "LIST", "NOLIST"];
+
+/// Lines at the top of every page: the title, the subtitle, a blank line and the column headings.
+const HEADER_LINES: usize = 4;
+/// The shortest page that still has room for a line under the headers.
+pub const MIN_PAGE_LENGTH: usize = HEADER_LINES + 1;
+/// Hex digits of object code shown next to the source, the rest goes on continuation lines.
+const CODE_WIDTH: usize = 8;
+
+/// A directive that only changes how the listing looks. These generate nothing and take no space.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListingControl {
+    Title(String),
+    Subtitle(String),
+    Eject,
+    Space(usize),
+    List,
+    NoList,
+}
+
+impl ListingControl {
+    /// The listing directive on the line, if it is one.
+    pub fn parse(line_parts: &LineParts) -> Option<Self> {
+        let directive = line_parts.opcode.to_uppercase();
+        if !LISTING_DIRECTIVES.contains(&directive.as_str()) {
+            return None;
+        }
+        if !line_parts.label.is_empty() {
+            panic!("{} doesn't take a label", directive)
+        }
+        let operand = line_parts.operand.as_str();
+        let control = match directive.as_str() {
+            "TITLE" => ListingControl::Title(parse_quoted(&directive, operand)),
+            "SUBTTL" => ListingControl::Subtitle(parse_quoted(&directive, operand)),
+            "SPACE" if operand.is_empty() => ListingControl::Space(1),
+            "SPACE" => ListingControl::Space(operand.parse::<usize>().unwrap_or_else(|_| panic!("SPACE needs a number of lines, not {}", operand))),
+            _ if !operand.is_empty() => panic!("{} doesn't take an operand", directive),
+            "EJECT" => ListingControl::Eject,
+            "LIST" => ListingControl::List,
+            _ => ListingControl::NoList,
+        };
+        Some(control)
+    }
+}
+
+/// Writes the printed listing: the line number, location, object code and text of every source
+/// line, split into pages of `page_length` lines that each start with the title and subtitle.
+pub fn write_listing(global_map: &GlobalMap, lines: &[SourceLine], asm_lines: &[ASMLine], object_codes: &[Option<ObjectCode>], page_length: usize) -> String {
+    let asm_line_at = asm_lines.iter().enumerate().map(|(idx, line)| (line.line_no, idx)).collect::<HashMap<usize, usize>>();
+    let mut page = Page { length: page_length, title: String::new(), subtitle: String::new(), number: 0, used: page_length, text: String::new() };
+    let mut listing = true;
+    for (idx, line) in lines.iter().enumerate() {
+        let line_no = idx + 1;
+        match ListingControl::parse(&split_line(global_map, &line.text)) {
+            Some(ListingControl::Title(title)) => {
+                page.title = title;
+                page.eject();
+            }
+            Some(ListingControl::Subtitle(subtitle)) => {
+                page.subtitle = subtitle;
+                page.eject();
+            }
+            Some(ListingControl::Eject) => page.eject(),
+            Some(ListingControl::Space(count)) if listing => (0..count).for_each(|_| page.push(String::new())),
+            Some(ListingControl::List) => listing = true,
+            Some(ListingControl::NoList) => listing = false,
+            Some(ListingControl::Space(_)) => {}
+            None if listing => {
+                let asm_line = asm_line_at.get(&line_no).map(|&idx| &asm_lines[idx]);
+                let code = asm_line_at.get(&line_no).and_then(|&idx| object_codes.get(idx)).and_then(Option::as_ref).map(|code| code.code.as_str()).unwrap_or_default();
+                let loc = asm_line.map(|line| format!("{:06X}", line.loc)).unwrap_or_default();
+                let mut chunks = code.as_bytes().chunks(CODE_WIDTH).map(|chunk| String::from_utf8_lossy(chunk).into_owned());
+                page.push(format!("{:>5}  {:<6}  {:<8}  {}", line_no, loc, chunks.next().unwrap_or_default(), line.text).trim_end().to_string());
+                for chunk in chunks {
+                    page.push(format!("{:>5}  {:<6}  {}", "", "", chunk));
+                }
+            }
+            None => {}
+        }
+    }
+    page.text
+}
+
+struct Page {
+    length: usize,
+    title: String,
+    subtitle: String,
+    number: usize,
+    //lines printed on the current page, headers included
+    used: usize,
+    text: String,
+}
+
+impl Page {
+    fn push(&mut self, line: String) {
+        if self.used >= self.length {
+            self.start();
+        }
+        self.text += &line;
+        self.text.push('\n');
+        self.used += 1;
+    }
+
+    fn start(&mut self) {
+        self.number += 1;
+        if self.number > 1 {
+            self.text.push('\x0c');
+        }
+        self.text += &format!("{:<64}PAGE {:>4}\n{}\n\n{:>5}  {:<6}  {:<8}  {}\n", self.title, self.number, self.subtitle, "LINE", "LOC", "OBJECT", "SOURCE");
+        self.used = HEADER_LINES;
+    }
+
+    /// Starts a new page with the next listed line, unless nothing has been listed on this one yet.
+    fn eject(&mut self) {
+        if self.used > HEADER_LINES {
+            self.used = self.length;
+        }
+    }
+}
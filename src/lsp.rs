@@ -0,0 +1,382 @@
+//! Language server for SIC/XE source over stdio. Documents are synced in full on every change and
+//! re-assembled to publish diagnostics. Positions are counted in characters, which matches the
+//! UTF-16 offsets of the protocol for the ASCII source the assembler accepts.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic;
+use crate::assembler::{check_assertions, check_symbols, pass_one, pass_two};
+use crate::batch::{expand_includes, SourceLine};
+use crate::diagnostics::{catch_diagnostic, Diagnostic, Severity, SourceLocation};
+use crate::global_map::{get_opcode_description, GlobalMap};
+use crate::json::Json;
+use crate::parse_utils::*;
+
+struct Document {
+    text: String,
+    global_map: Option<GlobalMap>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// A label written in the document, either where it is defined or where an operand refers to it.
+/// `$NAME` labels are named with their scope, as pass 1 names them, so `width` is how many
+/// characters the label takes in the text.
+struct Symbol {
+    name: String,
+    line: usize,
+    column: usize,
+    width: usize,
+    is_definition: bool,
+}
+
+pub fn run() {
+    //errors are reported as diagnostics, so the default panic message would only clutter stderr
+    panic::set_hook(Box::new(|_| {}));
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
+    let mut documents: HashMap<String, Document> = HashMap::new();
+    while let Some(message) = read_message(&mut reader) {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or_default().to_string();
+        let id = message.get("id").cloned();
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let result = match method.as_str() {
+            "initialize" => Some(initialize_result()),
+            "shutdown" => Some(Json::Null),
+            "exit" => break,
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let uri = document_uri(&params);
+                let text = match method.as_str() {
+                    "textDocument/didOpen" => params.get("textDocument").and_then(|document| document.get("text")),
+                    _ => match params.get("contentChanges") {
+                        Some(Json::Array(changes)) => changes.last().and_then(|change| change.get("text")),
+                        _ => None,
+                    },
+                }.and_then(Json::as_str).unwrap_or_default().to_string();
+                let document = analyze(&uri, text);
+                publish_diagnostics(&uri, &document.text, &document.diagnostics);
+                documents.insert(uri, document);
+                None
+            }
+            "textDocument/didClose" => {
+                let uri = document_uri(&params);
+                documents.remove(&uri);
+                publish_diagnostics(&uri, "", &[]);
+                None
+            }
+            "textDocument/definition" | "textDocument/references" | "textDocument/hover" | "textDocument/completion" | "textDocument/documentSymbol" => {
+                let uri = document_uri(&params);
+                let position = get_position(&params);
+                Some(match documents.get(&uri) {
+                    None => Json::Null,
+                    Some(document) => match method.as_str() {
+                        "textDocument/definition" => definition(&uri, document, position),
+                        "textDocument/references" => references(&uri, document, position),
+                        "textDocument/hover" => hover(document, position),
+                        "textDocument/completion" => completion(document),
+                        _ => document_symbols(document),
+                    },
+                })
+            }
+            _ => None,
+        };
+        let Some(id) = id else {
+            continue;
+        };
+        match result {
+            Some(result) => send(Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id), ("result", result)])),
+            None => send(Json::object(vec![
+                ("jsonrpc", Json::string("2.0")),
+                ("id", id),
+                ("error", Json::object(vec![
+                    ("code", Json::Number(-32601)),
+                    ("message", Json::string(format!("Unsupported method {}", method))),
+                ])),
+            ])),
+        }
+    }
+}
+
+fn read_message(reader: &mut impl BufRead) -> Option<Json> {
+    loop {
+        let mut content_length = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(length) = header.strip_prefix("Content-Length:") {
+                content_length = length.trim().parse::<usize>().ok();
+            }
+        }
+        let mut body = vec![0; content_length?];
+        reader.read_exact(&mut body).ok()?;
+        if let Ok(message) = Json::parse(&String::from_utf8_lossy(&body)) {
+            return Some(message);
+        }
+    }
+}
+
+fn send(message: Json) {
+    let body = message.to_json_string();
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body).expect("Couldn't write to stdout");
+    stdout.flush().expect("Couldn't write to stdout");
+}
+
+fn initialize_result() -> Json {
+    Json::object(vec![
+        ("capabilities", Json::object(vec![
+            ("textDocumentSync", Json::Number(1)),
+            ("definitionProvider", Json::Bool(true)),
+            ("referencesProvider", Json::Bool(true)),
+            ("hoverProvider", Json::Bool(true)),
+            ("completionProvider", Json::object(vec![])),
+            ("documentSymbolProvider", Json::Bool(true)),
+        ])),
+        ("serverInfo", Json::object(vec![
+            ("name", Json::string("sic_xe_lsp")),
+            ("version", Json::string(env!("CARGO_PKG_VERSION"))),
+        ])),
+    ])
+}
+
+fn document_uri(params: &Json) -> String {
+    params.get("textDocument").and_then(|document| document.get("uri")).and_then(Json::as_str).unwrap_or_default().to_string()
+}
+
+fn get_position(params: &Json) -> (usize, usize) {
+    let position = params.get("position");
+    let get = |key| position.and_then(|position| position.get(key)).and_then(Json::as_i64).unwrap_or(0) as usize;
+    (get("line"), get("character"))
+}
+
+fn uri_to_path(uri: &str) -> String {
+    uri.strip_prefix("file://").unwrap_or(uri).replace("%20", " ")
+}
+
+/// Assembles the document and keeps the symbols of pass 1 for hover. Only the first error can be
+/// reported, since the assembler stops at it.
+fn analyze(uri: &str, text: String) -> Document {
+    let file = uri_to_path(uri);
+    let lines = text.lines().enumerate().map(|(idx, line)| SourceLine {
+        text: line.to_string(),
+        location: SourceLocation { file: file.clone(), line_no: idx + 1 },
+    }).collect::<Vec<SourceLine>>();
+    let result = catch_diagnostic(&file, || {
+        let lines = expand_includes(lines, &[], &mut Vec::new());
+        let mut global_map = GlobalMap::init();
+        let asm_lines = pass_one(&mut global_map, &lines, &Default::default());
+        let mut errors = check_symbols(&global_map, &asm_lines);
+        if errors.is_empty() {
+            errors.extend(check_assertions(&global_map, &asm_lines));
+        }
+        if !errors.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+            pass_two(&global_map, &asm_lines);
+        }
+        (global_map, errors)
+    });
+    match result {
+        Ok((global_map, errors)) => Document { text, global_map: Some(global_map), diagnostics: errors },
+        Err(diagnostic) => Document { text, global_map: None, diagnostics: vec![diagnostic] },
+    }
+}
+
+fn publish_diagnostics(uri: &str, text: &str, diagnostics: &[Diagnostic]) {
+    let file = uri_to_path(uri);
+    let lines = text.lines().collect::<Vec<&str>>();
+    let diagnostics = diagnostics.iter().map(|diagnostic| {
+        //errors inside an included file are shown on the first line of this document
+        let line = if diagnostic.location.file == file { diagnostic.location.line_no.saturating_sub(1) } else { 0 };
+        let message = if diagnostic.location.file == file { diagnostic.message.clone() } else { format!("{}", diagnostic) };
+        Json::object(vec![
+            ("range", range_json(line, 0, line, lines.get(line).map_or(0, |line| line.chars().count()))),
+            ("severity", Json::Number(match diagnostic.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+                Severity::Note => 3,
+            })),
+            ("source", Json::string("sic_xe")),
+            ("message", Json::string(message)),
+        ])
+    }).collect();
+    send(Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("method", Json::string("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![
+            ("uri", Json::string(uri)),
+            ("diagnostics", Json::Array(diagnostics)),
+        ])),
+    ]));
+}
+
+fn range_json(start_line: usize, start_column: usize, end_line: usize, end_column: usize) -> Json {
+    let position = |line: usize, column: usize| Json::object(vec![("line", Json::number(line)), ("character", Json::number(column))]);
+    Json::object(vec![("start", position(start_line, start_column)), ("end", position(end_line, end_column))])
+}
+
+fn location_json(uri: &str, symbol: &Symbol) -> Json {
+    Json::object(vec![
+        ("uri", Json::string(uri)),
+        ("range", range_json(symbol.line, symbol.column, symbol.line, symbol.column + symbol.width)),
+    ])
+}
+
+/// The label an operand refers to, if any, without its addressing prefix or index suffix.
+fn operand_label(operand: &str) -> Option<&str> {
+    let label = operand.trim_start_matches(['#', '@']);
+    let label = label.strip_suffix(",X").unwrap_or(label);
+    let is_label = label.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '$')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !operand.contains('\'');
+    is_label.then_some(label)
+}
+
+fn get_symbols(text: &str) -> Vec<Symbol> {
+    let global_map = GlobalMap::init();
+    let mut symbols = Vec::new();
+    let mut scope = String::new();
+    let qualify = |scope: &str, label: &str| if label.starts_with('$') { format!("{}{}", scope, label) } else { label.to_string() };
+    for (line, line_text) in text.lines().enumerate() {
+        let parts = split_line(&global_map, line_text);
+        let scope_above = scope.clone();
+        if !parts.label.is_empty() {
+            let is_numeric = parts.label.len() == 2 && parts.label.starts_with(|c: char| c.is_ascii_digit()) && parts.label.ends_with(['H', 'h']);
+            if !parts.label.starts_with('$') && !is_numeric {
+                scope = parts.label.clone();
+            }
+            let column = line_text.find(&parts.label).unwrap_or(0);
+            symbols.push(Symbol { name: qualify(&scope, &parts.label), line, column, width: parts.label.chars().count(), is_definition: true });
+        }
+        let is_format_two = global_map.opcode_map.get(&parts.opcode.trim_start_matches('+').to_uppercase()).is_some_and(|detail| detail.format == OpcodeFormat::Two);
+        if let Some(label) = operand_label(&parts.operand).filter(|_| !is_format_two) {
+            //the operand comes after the opcode, with any spaces around its commas removed
+            let label_end = line_text.find(&parts.label).map_or(0, |column| column + parts.label.len());
+            let opcode_end = line_text[label_end..].find(&parts.opcode).map_or(label_end, |column| label_end + column + parts.opcode.len());
+            let column = opcode_end + line_text[opcode_end..].find(label).unwrap_or(0);
+            //as in the assembler, an EQU's own operand stays in the scope above it
+            let operand_scope = if parts.opcode.eq_ignore_ascii_case("EQU") { &scope_above } else { &scope };
+            symbols.push(Symbol { name: qualify(operand_scope, label), line, column, width: label.chars().count(), is_definition: false });
+        }
+    }
+    symbols
+}
+
+/// The word under the cursor. Labels are named as get_symbols names them, so `$NAME` comes with its scope.
+fn word_at(text: &str, (line, column): (usize, usize)) -> Option<String> {
+    let symbol = get_symbols(text).into_iter().find(|symbol| symbol.line == line && (symbol.column..=symbol.column + symbol.width).contains(&column));
+    if let Some(symbol) = symbol {
+        return Some(symbol.name);
+    }
+    let chars = text.lines().nth(line)?.chars().collect::<Vec<char>>();
+    let is_word = |c: &char| c.is_ascii_alphanumeric() || *c == '_' || *c == '$';
+    let column = column.min(chars.len());
+    let start = chars[..column].iter().rposition(|c| !is_word(c)).map_or(0, |idx| idx + 1);
+    let end = chars[column..].iter().position(|c| !is_word(c)).map_or(chars.len(), |idx| column + idx);
+    (start < end).then(|| chars[start..end].iter().collect())
+}
+
+fn definition(uri: &str, document: &Document, position: (usize, usize)) -> Json {
+    let Some(word) = word_at(&document.text, position) else {
+        return Json::Null;
+    };
+    get_symbols(&document.text).iter()
+        .find(|symbol| symbol.is_definition && symbol.name == word)
+        .map_or(Json::Null, |symbol| location_json(uri, symbol))
+}
+
+fn references(uri: &str, document: &Document, position: (usize, usize)) -> Json {
+    let Some(word) = word_at(&document.text, position) else {
+        return Json::Array(Vec::new());
+    };
+    Json::Array(get_symbols(&document.text).iter()
+        .filter(|symbol| symbol.name == word)
+        .map(|symbol| location_json(uri, symbol))
+        .collect())
+}
+
+fn hover(document: &Document, position: (usize, usize)) -> Json {
+    let Some(word) = word_at(&document.text, position) else {
+        return Json::Null;
+    };
+    let global_map = GlobalMap::init();
+    let symbols = get_symbols(&document.text);
+    let definition = symbols.iter().find(|symbol| symbol.is_definition && symbol.name == word);
+    let contents = if let Some(definition) = definition {
+        let address = document.global_map.as_ref().and_then(|global_map| global_map.label_map.get(&word));
+        match address {
+            Some(address) => format!("**{}** address {:06X} ({})", word, address, address),
+            None => format!("**{}** defined on line {}", word, definition.line + 1),
+        }
+    } else if let Some(detail) = global_map.opcode_map.get(&word.to_uppercase()) {
+        let format = match detail.format {
+            OpcodeFormat::One => "1",
+            OpcodeFormat::Two => "2",
+            OpcodeFormat::Three | OpcodeFormat::Four => "3/4",
+        };
+        let description = get_opcode_description(&word.to_uppercase()).unwrap_or_default();
+        format!("**{}** opcode {:02X}, format {}\n\n{}", word, detail.opcode, format, description)
+    } else if is_directive(&word) {
+        format!("**{}** assembler directive", word)
+    } else if let Some(register) = global_map.register_map.get(&word.to_uppercase()) {
+        format!("**{}** register {}", word, register)
+    } else {
+        return Json::Null;
+    };
+    Json::object(vec![("contents", Json::object(vec![
+        ("kind", Json::string("markdown")),
+        ("value", Json::string(contents)),
+    ]))])
+}
+
+fn completion(document: &Document) -> Json {
+    const KEYWORD: i64 = 14;
+    const VARIABLE: i64 = 6;
+    const REFERENCE: i64 = 18;
+    let global_map = GlobalMap::init();
+    let item = |label: &str, kind: i64, detail: String| Json::object(vec![
+        ("label", Json::string(label)),
+        ("kind", Json::Number(kind)),
+        ("detail", Json::string(detail)),
+    ]);
+    let mut mnemonics = global_map.opcode_map.iter().collect::<Vec<_>>();
+    mnemonics.sort_by_key(|(mnemonic, _)| mnemonic.as_str());
+    let mut registers = global_map.register_map.iter().collect::<Vec<_>>();
+    registers.sort_by_key(|(_, value)| **value);
+    let mut items = mnemonics.into_iter()
+        .map(|(mnemonic, detail)| item(mnemonic, KEYWORD, get_opcode_description(mnemonic).map_or_else(|| format!("opcode {:02X}", detail.opcode), String::from)))
+        .collect::<Vec<Json>>();
+    items.extend(DIRECTIVES.iter().map(|directive| item(directive, KEYWORD, String::from("directive"))));
+    items.extend(registers.into_iter().map(|(register, value)| item(register, VARIABLE, format!("register {}", value))));
+    let mut labels = get_symbols(&document.text).into_iter().filter(|symbol| symbol.is_definition).map(|symbol| symbol.name).collect::<Vec<String>>();
+    labels.sort();
+    labels.dedup();
+    items.extend(labels.iter().map(|label| item(label, REFERENCE, String::from("label"))));
+    Json::Array(items)
+}
+
+fn document_symbols(document: &Document) -> Json {
+    const MODULE: i64 = 2;
+    const FUNCTION: i64 = 12;
+    const VARIABLE: i64 = 13;
+    let global_map = GlobalMap::init();
+    let lines = document.text.lines().collect::<Vec<&str>>();
+    Json::Array(get_symbols(&document.text).iter().filter(|symbol| symbol.is_definition).map(|symbol| {
+        let line_text = lines[symbol.line];
+        let opcode = split_line(&global_map, line_text).opcode;
+        let kind = match opcode.as_str() {
+            "START" => MODULE,
+            "RESW" | "RESB" | "WORD" | "BYTE" => VARIABLE,
+            _ => FUNCTION,
+        };
+        Json::object(vec![
+            ("name", Json::string(&symbol.name)),
+            ("detail", Json::string(opcode)),
+            ("kind", Json::Number(kind)),
+            ("range", range_json(symbol.line, 0, symbol.line, line_text.chars().count())),
+            ("selectionRange", range_json(symbol.line, symbol.column, symbol.line, symbol.column + symbol.width)),
+        ])
+    }).collect())
+}
@@ -1,143 +1,214 @@
-use std::{env, panic};
-use crate::batch::batch_mode;
-use crate::global_map::*;
-use crate::interactive::interactive_mode;
-use crate::parse_utils::*;
+use std::collections::HashSet;
+use std::{env, fs, process};
+use std::path::Path;
+use sic_xe_assembler::assembler::{check_assertions, check_base, check_label_case, check_symbols, pass_one, pass_two, relax};
+use sic_xe_assembler::batch::{batch_mode, SourceLine};
+use sic_xe_assembler::cfg::ControlFlowGraph;
+use sic_xe_assembler::diagnostics::{catch_diagnostic, Diagnostic, Severity};
+use sic_xe_assembler::explain::explain;
+use sic_xe_assembler::formatter::format_source;
+use sic_xe_assembler::global_map::*;
+use sic_xe_assembler::interactive::interactive_mode;
+use sic_xe_assembler::isa::load_isa;
+use sic_xe_assembler::object_program::ObjectProgram;
+use sic_xe_assembler::json_report::json_report;
+use sic_xe_assembler::lint::lint;
+use sic_xe_assembler::listing::write_listing;
+use sic_xe_assembler::options::{Command, Options, ReportFormat};
+use sic_xe_assembler::output::write_output;
+use sic_xe_assembler::parse_utils::*;
+use sic_xe_assembler::symbols::write_symbols;
 
-mod utils;
-mod global_map;
-mod nixbpe;
-mod parse_utils;
-mod interactive;
-mod batch;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Usage: ./sic_xe_assembler <filename>||-i")
-    }
-    let filename = &args[1];
-    let lines = if filename == "-i" {
-        interactive_mode()
-    } else {
-        batch_mode(filename)
-    };
-
-    let mut global_map = GlobalMap::init();
-    let mut loc = 0;
-    let mut loc_inc = 0;
-    let mut asm_lines: Vec<ASMLine> = Vec::new();
-    // let mut start: usize = 0;
-    //PASS 1
-    for line in lines.into_iter().enumerate().map(|line| (line.0 + 1, line.1)) {
-        let line_parts = line.1.split(' ').collect::<Vec<&str>>();
-        let label: &str;
-        let opcode: &str;
-        let address: &str;
-        if line_parts.len() >= 3 {
-            label = line_parts[0];
-            opcode = line_parts[1];
-            address = line_parts[2];
-        } else if line_parts.len() == 2 {
-            let t = line_parts[0];
-            if panic::catch_unwind(|| parse_opcode(&global_map, t.to_string())).is_ok()
-            {
-                label = "";
-                opcode = t;
-                address = line_parts[1];
-            } else {
-                label = t;
-                opcode = line_parts[1];
-                address = "";
-            }
-        } else {
-            label = "";
-            opcode = line_parts[0];
-            address = "";
+    let options = Options::parse(env::args().collect());
+    match options.command {
+        Command::Verify => {
+            verify(&options.filename);
+            return;
         }
-        let address_spec = if !address.is_empty() {
-            parse_address(&global_map, address.to_string())
-        } else {
-            AddressSpec::Address(0, AddressingModes::None)
-        };
-        let opcode_spec = if !opcode.is_empty() {
-            parse_opcode(&global_map, opcode.to_string())
+        Command::Format => {
+            format_files(&options);
+            return;
+        }
+        Command::Assemble => {}
+    }
+    //a JSON consumer gets a report even when assembling panics, with the error as its diagnostic
+    let assemble = || {
+        let lines = if options.filename == "-i" {
+            interactive_mode(&options.include_dirs)
         } else {
-            panic!("No opcode")
+            batch_mode(&options.filename, &options.include_dirs)
         };
-        loc += loc_inc;
-        if line.0 == 1 {
-            if let OpcodeSpec::Directive(directive) = &opcode_spec {
-                if directive == "START" {
-                    if let AddressSpec::Address(address, _) = &address_spec {
-                        loc = *address;
-                        // start = *address;
-                        if !label.is_empty() {
-                            global_map.label_map.insert(label.to_string(), loc);
-                        }
-                    } else {
-                        panic!("Invalid address for start")
-                    }
+
+        let mut global_map = GlobalMap::init();
+        global_map.label_case = options.label_case;
+        global_map.include_dirs = options.include_dirs.clone();
+        global_map.charset = options.charset;
+        if let Some(isa_file) = &options.isa_file {
+            load_isa(&mut global_map, isa_file);
+        }
+        let mut diagnostics = Vec::new();
+        //PASS 1
+        let (global_map, asm_lines) = if options.auto_extend {
+            let (global_map, asm_lines, promoted) = relax(&global_map, &lines);
+            for line_no in promoted {
+                if let Some(ASMLine { opcode_spec: OpcodeSpec::Opcode(opcode, _), location, .. }) = asm_lines.iter().find(|line| line.line_no == line_no) {
+                    diagnostics.push(Diagnostic::new(Severity::Note, location, format!("promoted {} to format 4", opcode)));
                 }
-            } else {
-                panic!("First line should be a START")
             }
+            (global_map, asm_lines)
         } else {
-            if let OpcodeSpec::Directive(directive) = &opcode_spec{
-                if directive == "LTORG" {
-                    global_map.literal_pool = Vec::new();
-                    for lit in &global_map.literal_pool {
-                        global_map.literal_map.insert(lit.clone(),loc);
-                        loc += lit.get_len();
-                    }
-                }
+            let mut global_map = global_map;
+            let asm_lines = pass_one(&mut global_map, &lines, &HashSet::new());
+            (global_map, asm_lines)
+        };
+
+        let symbol_errors = check_symbols(&global_map, &asm_lines);
+        if symbol_errors.is_empty() {
+            diagnostics.extend(check_assertions(&global_map, &asm_lines));
+        }
+        diagnostics.extend(symbol_errors);
+        (lines, global_map, asm_lines, diagnostics)
+    };
+    let (lines, global_map, asm_lines, mut diagnostics) = match options.format {
+        ReportFormat::Listing => assemble(),
+        ReportFormat::Json => catch_diagnostic(&options.filename, assemble).unwrap_or_else(|error| fail_json(&GlobalMap::init(), &[], &[], &[error])),
+    };
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        match options.format {
+            ReportFormat::Listing => diagnostics.iter().for_each(|diagnostic| println!("{}", diagnostic)),
+            ReportFormat::Json => fail_json(&global_map, &lines, &asm_lines, &diagnostics),
+        }
+        process::exit(1);
+    }
+    let check_and_pass_two = || {
+        let mut warnings = check_label_case(&asm_lines);
+        if options.check_base {
+            warnings.extend(check_base(&global_map, &asm_lines));
+        }
+        if !options.lints.is_empty() {
+            warnings.extend(lint(&global_map, &asm_lines, &options.lints));
+        }
+        //PASS 2
+        (warnings, pass_two(&global_map, &asm_lines))
+    };
+    let (warnings, object_codes) = match options.format {
+        ReportFormat::Listing => check_and_pass_two(),
+        ReportFormat::Json => catch_diagnostic(&options.filename, check_and_pass_two).unwrap_or_else(|error| {
+            diagnostics.push(error);
+            fail_json(&global_map, &lines, &asm_lines, &diagnostics)
+        }),
+    };
+    diagnostics.extend(warnings);
+    match options.format {
+        ReportFormat::Listing => {
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic);
             }
-            if let AddressSpec::Literal(literal) = &address_spec{
-                global_map.literal_pool.push(literal.clone());
+            for asm in asm_lines.clone(){
+                println!("{:#?}",asm);
             }
-
-            loc_inc = get_loc_inc(&opcode_spec, &address_spec);
-            if !label.is_empty() {
-                global_map.label_map.insert(label.to_string(), loc);
-                if let AddressSpec::Constant(constant) = &address_spec {
-                    global_map.constant_map.insert(label.to_string(), constant.clone());
+            for (idx, object_code) in object_codes.iter().enumerate() {
+                match object_code {
+                    None => {
+                        println!("Line : {} Object code: None", idx + 1);
+                    }
+                    Some(object_code) => {
+                        println!("Line : {} Object code: {}", idx + 1, object_code.code);
+                        if options.explain {
+                            for step in explain(&global_map, &asm_lines[idx], object_code).unwrap_or_default() {
+                                println!("    {}", step);
+                            }
+                        }
+                    }
                 }
             }
         }
-        let asm_line = ASMLine {
-            opcode_spec: opcode_spec.clone(),
-            address_spec: address_spec.clone(),
-        };
-        asm_lines.push(asm_line);
+        ReportFormat::Json => {
+            println!("{}", json_report(&global_map, &lines, &asm_lines, &object_codes, &diagnostics).to_json_string());
+        }
     }
 
-    for asm in asm_lines.clone(){
-        println!("{:#?}",asm);
+    if let Some(listing_file) = &options.listing_file {
+        let listing = write_listing(&global_map, &lines, &asm_lines, &object_codes, options.page_length);
+        fs::write(listing_file, listing).unwrap_or_else(|err| panic!("Unable to write {}: {}", listing_file, err));
+        if options.format == ReportFormat::Listing {
+            println!("Wrote {}", listing_file);
+        }
     }
 
-    //PASS 2
-    let mut base = 0;
-    let mut pc = 0;
-
-    for line in asm_lines.iter().take(asm_lines.len() - 1).enumerate() {
-        let idx = line.0;
-        let line = line.1;
-        let ret = get_object_code(base, pc, &global_map, line);
-        let (object_code, new_base, new_pc) = ret;
-        base = new_base;
-        pc = new_pc;
-        match &object_code {
-            None => {
-                println!("Line : {} Object code: None", idx + 1);
-            }
-            Some(object_code) => {
-                println!("Line : {} Object code: {}", idx + 1, object_code);
-            }
+    if let Some(symbols_file) = &options.symbols_file {
+        let name = asm_lines.first().map(|line| line.label.as_str()).unwrap_or_default();
+        fs::write(symbols_file, write_symbols(&global_map, name)).unwrap_or_else(|err| panic!("Unable to write {}: {}", symbols_file, err));
+        if options.format == ReportFormat::Listing {
+            println!("Wrote {}", symbols_file);
         }
     }
-}
 
+    if let Some(cfg_file) = &options.cfg_file {
+        let dot = ControlFlowGraph::build(&global_map, &asm_lines).to_dot(&global_map, &asm_lines, &lines);
+        fs::write(cfg_file, dot).unwrap_or_else(|err| panic!("Unable to write {}: {}", cfg_file, err));
+        if options.format == ReportFormat::Listing {
+            println!("Wrote {}", cfg_file);
+        }
+    }
 
+    if let Some(output_format) = options.output_format {
+        let program = ObjectProgram::from_assembly(&global_map, &asm_lines, &object_codes);
+        let output_file = options.output_file.clone().unwrap_or_else(|| {
+            let input = if options.filename == "-i" { "a.sic" } else { &options.filename };
+            Path::new(input).with_extension(output_format.extension()).display().to_string()
+        });
+        fs::write(&output_file, write_output(&program, output_format, options.fill))
+            .unwrap_or_else(|err| panic!("Unable to write {}: {}", output_file, err));
+        if options.format == ReportFormat::Listing {
+            println!("Wrote {}", output_file);
+        }
+    }
+}
 
+/// Prints the JSON report of an assembly that stopped at an error, then exits.
+fn fail_json(global_map: &GlobalMap, lines: &[SourceLine], asm_lines: &[ASMLine], diagnostics: &[Diagnostic]) -> ! {
+    println!("{}", json_report(global_map, lines, asm_lines, &[], diagnostics).to_json_string());
+    process::exit(1)
+}
 
+fn verify(filename: &str) {
+    match ObjectProgram::read(filename) {
+        Ok(program) => {
+            println!("{}: OK", filename);
+            println!("Program {} start {:06X} length {:06X}", program.header.name, program.header.start, program.header.length);
+            println!("{} text records, {} modification records", program.texts.len(), program.modifications.len());
+        }
+        Err(err) => {
+            println!("{}: {}", filename, err);
+            process::exit(1);
+        }
+    }
+}
 
+fn format_files(options: &Options) {
+    let mut global_map = GlobalMap::init();
+    if let Some(isa_file) = &options.isa_file {
+        load_isa(&mut global_map, isa_file);
+    }
+    let mut unformatted = false;
+    for filename in &options.filenames {
+        let source = fs::read_to_string(filename).unwrap_or_else(|err| panic!("Unable to read {}: {}", filename, err));
+        let formatted = format_source(&global_map, &source, &options.format_options);
+        if formatted == source {
+            continue;
+        }
+        if options.check {
+            println!("{} is not formatted", filename);
+            unformatted = true;
+        } else {
+            fs::write(filename, formatted).unwrap_or_else(|err| panic!("Unable to write {}: {}", filename, err));
+            println!("Formatted {}", filename);
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
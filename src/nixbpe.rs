@@ -1,3 +1,4 @@
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Nixbpe {
     pub n: bool,
     pub i: bool,
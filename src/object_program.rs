@@ -0,0 +1,359 @@
+use std::fs;
+use crate::global_map::GlobalMap;
+use crate::parse_utils::{ASMLine, AddressSpec, ObjectCode, OpcodeFormat, OpcodeSpec};
+use crate::utils::hex_string_to_bytes;
+
+/// Most bytes a single T record can hold, since its length field is read as at most 1E.
+pub const MAX_TEXT_LEN: usize = 30;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub name: String,
+    pub start: usize,
+    pub length: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextRecord {
+    pub start: usize,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModificationRecord {
+    pub address: usize,
+    pub half_bytes: usize,
+    pub symbol: Option<(char, String)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectProgram {
+    pub header: Header,
+    pub defines: Vec<(String, usize)>,
+    pub refers: Vec<String>,
+    pub texts: Vec<TextRecord>,
+    pub modifications: Vec<ModificationRecord>,
+    pub first_instruction: Option<usize>,
+}
+
+impl ObjectProgram {
+    /// Builds the object program from pass 2 output. A new T record is started whenever the next
+    /// object code isn't contiguous (after RESB/RESW) or wouldn't fit in the current record. Format 4
+    /// instructions and WORDs holding a relative label get an M record, since their address field
+    /// holds an actual address that moves with the program.
+    pub fn from_assembly(global_map: &GlobalMap, asm_lines: &[ASMLine], object_codes: &[Option<ObjectCode>]) -> Self {
+        let first = asm_lines.first().expect("Empty program");
+        let last = asm_lines.last().expect("Empty program");
+        let mut texts: Vec<TextRecord> = Vec::new();
+        let mut modifications = Vec::new();
+        for (line, object_code) in asm_lines.iter().zip(object_codes) {
+            let Some(object_code) = object_code else {
+                continue;
+            };
+            if let AddressSpec::Label(label, _) = &line.address_spec {
+                let field = match &line.opcode_spec {
+                    OpcodeSpec::Opcode(_, OpcodeFormat::Four) => Some((line.loc + 1, 5)),
+                    OpcodeSpec::Directive(directive) if directive == "WORD" => Some((line.loc, 6)),
+                    _ => None,
+                };
+                if let Some((address, half_bytes)) = field.filter(|_| !global_map.is_absolute(label)) {
+                    modifications.push(ModificationRecord { address, half_bytes, symbol: None });
+                }
+            }
+            let bytes = hex_string_to_bytes(&object_code.code);
+            let fits = texts.last().is_some_and(|text| text.start + text.bytes.len() == line.loc && text.bytes.len() + bytes.len() <= MAX_TEXT_LEN);
+            if fits {
+                texts.last_mut().unwrap().bytes.extend(bytes);
+                continue;
+            }
+            //data longer than a record, like a long WORD list, is spread over as many as it needs
+            for (idx, chunk) in bytes.chunks(MAX_TEXT_LEN).enumerate() {
+                texts.push(TextRecord { start: line.loc + idx * MAX_TEXT_LEN, bytes: chunk.to_vec() });
+            }
+        }
+        //an undefined END label is reported by check_symbols, the program is still built for the JSON report
+        let first_instruction = match (&last.opcode_spec, &last.address_spec) {
+            (OpcodeSpec::Directive(directive), AddressSpec::Label(label, _)) if directive == "END" => {
+                global_map.label_map.get(label).copied()
+            }
+            _ => Some(first.loc),
+        };
+        ObjectProgram {
+            header: Header { name: first.label.clone(), start: first.loc, length: last.loc - first.loc },
+            defines: Vec::new(),
+            refers: Vec::new(),
+            texts,
+            modifications,
+            first_instruction,
+        }
+    }
+
+    /// Writes the H/D/R/T/M/E records, one per line.
+    pub fn to_records(&self) -> String {
+        let mut records = format!("H{:<6}{:06X}{:06X}\n", truncate(&self.header.name, 6), self.header.start, self.header.length);
+        if !self.defines.is_empty() {
+            records += "D";
+            for (name, address) in &self.defines {
+                records += &format!("{:<6}{:06X}", truncate(name, 6), address);
+            }
+            records += "\n";
+        }
+        if !self.refers.is_empty() {
+            records += "R";
+            for name in &self.refers {
+                records += &format!("{:<6}", truncate(name, 6));
+            }
+            records += "\n";
+        }
+        for text in &self.texts {
+            records += &format!("T{:06X}{:02X}", text.start, text.bytes.len());
+            for byte in &text.bytes {
+                records += &format!("{:02X}", byte);
+            }
+            records += "\n";
+        }
+        for modification in &self.modifications {
+            records += &format!("M{:06X}{:02X}", modification.address, modification.half_bytes);
+            if let Some((sign, symbol)) = &modification.symbol {
+                records += &format!("{}{}", sign, symbol);
+            }
+            records += "\n";
+        }
+        match self.first_instruction {
+            Some(address) => records += &format!("E{:06X}\n", address),
+            None => records += "E\n",
+        }
+        records
+    }
+
+    pub fn read(filename: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(filename).map_err(|err| format!("Unable to read {}: {}", filename, err))?;
+        Self::parse(&text)
+    }
+
+    /// Parses H/D/R/T/M/E records, one per line. `^` separators between fields are accepted and ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut header: Option<Header> = None;
+        let mut program = ObjectProgram {
+            header: Header { name: String::new(), start: 0, length: 0 },
+            defines: Vec::new(),
+            refers: Vec::new(),
+            texts: Vec::new(),
+            modifications: Vec::new(),
+            first_instruction: None,
+        };
+        let mut ended = false;
+        for (idx, line) in text.lines().enumerate() {
+            let record = line.replace('^', "");
+            let record = record.trim_end();
+            if record.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", idx + 1, message);
+            if !record.is_ascii() {
+                return Err(error(String::from("Non-ASCII characters in record")));
+            }
+            if ended {
+                return Err(error(String::from("Record after the E record")));
+            }
+            let kind = record.chars().next().unwrap();
+            if kind != 'H' && header.is_none() {
+                return Err(error(String::from("The first record must be an H record")));
+            }
+            let body = &record[kind.len_utf8()..];
+            match kind {
+                'H' => {
+                    if header.is_some() {
+                        return Err(error(String::from("More than one H record")));
+                    }
+                    expect_len(body, 18).map_err(error)?;
+                    let start = parse_hex(&body[6..12]).map_err(error)?;
+                    let length = parse_hex(&body[12..18]).map_err(error)?;
+                    if start + length > 1 << 20 {
+                        return Err(error(format!("Program {:X}+{:X} doesn't fit in memory", start, length)));
+                    }
+                    header = Some(Header { name: body[0..6].trim_end().to_string(), start, length });
+                }
+                'D' => {
+                    if body.is_empty() || body.len() % 12 != 0 {
+                        return Err(error(format!("D record length {} isn't a multiple of 12", body.len())));
+                    }
+                    for chunk in 0..body.len() / 12 {
+                        let entry = &body[chunk * 12..chunk * 12 + 12];
+                        program.defines.push((entry[0..6].trim_end().to_string(), parse_hex(&entry[6..12]).map_err(error)?));
+                    }
+                }
+                'R' => {
+                    if body.is_empty() {
+                        return Err(error(String::from("Empty R record")));
+                    }
+                    for chunk in body.as_bytes().chunks(6) {
+                        program.refers.push(String::from_utf8_lossy(chunk).trim_end().to_string());
+                    }
+                }
+                'T' => {
+                    if body.len() < 8 {
+                        return Err(error(format!("T record is {} characters, expected at least 8", body.len())));
+                    }
+                    let start = parse_hex(&body[0..6]).map_err(error)?;
+                    let len = parse_hex(&body[6..8]).map_err(error)?;
+                    if len > MAX_TEXT_LEN {
+                        return Err(error(format!("T record holds {} bytes, at most {} are allowed", len, MAX_TEXT_LEN)));
+                    }
+                    expect_len(&body[8..], len * 2).map_err(error)?;
+                    let bytes = (0..len).map(|i| parse_hex(&body[8 + i * 2..10 + i * 2]).map(|byte| byte as u8)).collect::<Result<Vec<u8>, String>>().map_err(error)?;
+                    program.texts.push(TextRecord { start, bytes });
+                }
+                'M' => {
+                    if body.len() < 8 {
+                        return Err(error(format!("M record is {} characters, expected at least 8", body.len())));
+                    }
+                    let address = parse_hex(&body[0..6]).map_err(error)?;
+                    let half_bytes = parse_hex(&body[6..8]).map_err(error)?;
+                    let symbol = match body[8..].chars().next() {
+                        None => None,
+                        Some(sign) if sign == '+' || sign == '-' => Some((sign, body[9..].trim_end().to_string())),
+                        Some(_) => return Err(error(format!("Invalid M record modification {}", &body[8..]))),
+                    };
+                    program.modifications.push(ModificationRecord { address, half_bytes, symbol });
+                }
+                'E' => {
+                    if !body.is_empty() {
+                        expect_len(body, 6).map_err(error)?;
+                        program.first_instruction = Some(parse_hex(body).map_err(error)?);
+                    }
+                    ended = true;
+                }
+                _ => return Err(error(format!("Unknown record type {}", kind))),
+            }
+        }
+        program.header = header.ok_or_else(|| String::from("Missing H record"))?;
+        if !ended {
+            return Err(String::from("Missing E record"));
+        }
+        program.validate()?;
+        Ok(program)
+    }
+
+    /// Checks that every record stays within the range declared in the H record.
+    pub fn validate(&self) -> Result<(), String> {
+        let start = self.header.start;
+        let end = self.header.start + self.header.length;
+        for text in &self.texts {
+            if text.start < start || text.start + text.bytes.len() > end {
+                return Err(format!("T record {:06X}-{:06X} is outside the program {:06X}-{:06X}", text.start, text.start + text.bytes.len(), start, end));
+            }
+        }
+        for modification in &self.modifications {
+            if modification.half_bytes != 5 && modification.half_bytes != 6 {
+                return Err(format!("M record at {:06X} modifies {} half-bytes, expected 05 or 06", modification.address, modification.half_bytes));
+            }
+            if modification.address < start || modification.address + modification.half_bytes.div_ceil(2) > end {
+                return Err(format!("M record at {:06X} is outside the program {:06X}-{:06X}", modification.address, start, end));
+            }
+        }
+        if let Some(first_instruction) = self.first_instruction {
+            if first_instruction < start || first_instruction >= end.max(start + 1) {
+                return Err(format!("E record address {:06X} is outside the program {:06X}-{:06X}", first_instruction, start, end));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn truncate(name: &str, len: usize) -> String {
+    name.chars().take(len).collect()
+}
+
+fn expect_len(field: &str, len: usize) -> Result<(), String> {
+    if field.len() != len {
+        return Err(format!("Expected {} characters, found {} in {}", len, field.len(), field));
+    }
+    Ok(())
+}
+
+fn parse_hex(field: &str) -> Result<usize, String> {
+    if field.is_empty() || !field.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid hex digits {}", field));
+    }
+    usize::from_str_radix(field, 16).map_err(|_| format!("Invalid hex digits {}", field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> ObjectProgram {
+        ObjectProgram {
+            header: Header { name: String::from("COPY"), start: 0x1000, length: 0x20 },
+            defines: Vec::new(),
+            refers: Vec::new(),
+            texts: vec![
+                TextRecord { start: 0x1000, bytes: vec![0x17, 0x20, 0x2D, 0x4B, 0x10, 0x10, 0x36] },
+                TextRecord { start: 0x1010, bytes: vec![0x45, 0x4F, 0x46] },
+            ],
+            modifications: vec![ModificationRecord { address: 0x1004, half_bytes: 5, symbol: None }],
+            first_instruction: Some(0x1000),
+        }
+    }
+
+    fn parse_error(records: &str) -> String {
+        ObjectProgram::parse(records).unwrap_err()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = program().to_records();
+        assert_eq!(records, "HCOPY  001000000020\nT0010000717202D4B101036\nT00101003454F46\nM00100405\nE001000\n");
+        assert_eq!(ObjectProgram::parse(&records), Ok(program()));
+    }
+
+    #[test]
+    fn separators_are_ignored() {
+        let parsed = ObjectProgram::parse("HCOPY  ^001000^000020\nT^001000^07^17202D4B101036\nT^001010^03^454F46\nM001004^05\nE^001000\n");
+        assert_eq!(parsed, Ok(program()));
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        let cases = [
+            ("T00100001FF\nE\n", "The first record must be an H record"),
+            ("HCOPY  001000000020\nHCOPY  001000000020\nE\n", "More than one H record"),
+            ("HCOPY  0010000000\nE\n", "Expected 18 characters"),
+            ("HCOPY  0FFFF0000020\nE\n", "doesn't fit in memory"),
+            ("HCOPY  00100G000020\nE\n", "Invalid hex digits 00100G"),
+            ("HCOPY  001000000020\nDBUF   00100\nE\n", "D record length 11 isn't a multiple of 12"),
+            ("HCOPY  001000000020\nR\nE\n", "Empty R record"),
+            ("HCOPY  001000000020\nT0010\nE\n", "T record is 4 characters, expected at least 8"),
+            ("HCOPY  001000000020\nT0010001F\nE\n", "T record holds 31 bytes, at most 30 are allowed"),
+            ("HCOPY  001000000020\nT00100002FF\nE\n", "Expected 4 characters, found 2"),
+            ("HCOPY  001000000020\nM0010\nE\n", "M record is 4 characters, expected at least 8"),
+            ("HCOPY  001000000020\nM00100405*COPY\nE\n", "Invalid M record modification *COPY"),
+            ("HCOPY  001000000020\nE0010\n", "Expected 6 characters, found 4"),
+            ("HCOPY  001000000020\nE\nT00100001FF\n", "Record after the E record"),
+            ("HCOPY  001000000020\nX\nE\n", "Unknown record type X"),
+            ("HCOPY  001000000020\nTÉ\nE\n", "Non-ASCII characters in record"),
+            ("", "Missing H record"),
+            ("HCOPY  001000000020\n", "Missing E record"),
+        ];
+        for (records, message) in cases {
+            let error = parse_error(records);
+            assert!(error.contains(message), "{:?} gave {:?}, expected {:?}", records, error, message);
+        }
+    }
+
+    #[test]
+    fn rejects_records_outside_the_program() {
+        let cases = [
+            ("HCOPY  001000000020\nT00101F02FFFF\nE\n", "T record 00101F-001021 is outside the program 001000-001020"),
+            ("HCOPY  001000000020\nT000FFF01FF\nE\n", "T record 000FFF-001000 is outside"),
+            ("HCOPY  001000000020\nM00100404\nE\n", "modifies 4 half-bytes, expected 05 or 06"),
+            ("HCOPY  001000000020\nM00101E06\nE\n", "M record at 00101E is outside"),
+            ("HCOPY  001000000020\nE001020\n", "E record address 001020 is outside"),
+        ];
+        for (records, message) in cases {
+            let error = parse_error(records);
+            assert!(error.contains(message), "{:?} gave {:?}, expected {:?}", records, error, message);
+        }
+    }
+}
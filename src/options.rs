@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use crate::charset::Charset;
+use crate::formatter::{Case, FormatOptions};
+use crate::global_map::LabelCase;
+use crate::lint::Lint;
+use crate::listing::MIN_PAGE_LENGTH;
+use crate::output::OutputFormat;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Assemble,
+    Verify,
+    Format,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Listing,
+    Json,
+}
+
+pub struct Options {
+    pub command: Command,
+    pub filename: String,
+    pub filenames: Vec<String>,
+    pub auto_extend: bool,
+    pub check_base: bool,
+    pub include_dirs: Vec<String>,
+    pub output_format: Option<OutputFormat>,
+    pub output_file: Option<String>,
+    pub fill: u8,
+    pub format: ReportFormat,
+    pub explain: bool,
+    pub check: bool,
+    pub format_options: FormatOptions,
+    pub lints: HashSet<Lint>,
+    pub cfg_file: Option<String>,
+    pub label_case: LabelCase,
+    pub listing_file: Option<String>,
+    pub page_length: usize,
+    pub symbols_file: Option<String>,
+    pub charset: Charset,
+    pub isa_file: Option<String>,
+}
+
+impl Options {
+    pub fn parse(args: Vec<String>) -> Self {
+        let mut options = Options {
+            command: Command::Assemble,
+            filename: String::new(),
+            filenames: Vec::new(),
+            auto_extend: false,
+            check_base: false,
+            include_dirs: Vec::new(),
+            output_format: None,
+            output_file: None,
+            fill: 0,
+            format: ReportFormat::Listing,
+            explain: false,
+            check: false,
+            format_options: FormatOptions::default(),
+            lints: HashSet::new(),
+            cfg_file: None,
+            label_case: LabelCase::Sensitive,
+            listing_file: None,
+            page_length: 60,
+            symbols_file: None,
+            charset: Charset::Ascii,
+            isa_file: None,
+        };
+        let mut args = args.into_iter().skip(1).peekable();
+        match args.peek().map(String::as_str) {
+            Some("verify") => options.command = Command::Verify,
+            Some("fmt") => options.command = Command::Format,
+            _ => {}
+        }
+        if options.command != Command::Assemble {
+            args.next();
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--auto-extend" => options.auto_extend = true,
+                "--check-base" => options.check_base = true,
+                "--explain" => options.explain = true,
+                "-I" => options.include_dirs.push(args.next().unwrap_or_else(|| print_usage())),
+                _ if arg.starts_with("-I") => options.include_dirs.push(arg[2..].to_string()),
+                "-Wall" => options.lints.extend(Lint::ALL),
+                _ if arg.starts_with("-Wno-") => {
+                    options.lints.remove(&Lint::parse(&arg[5..]));
+                }
+                _ if arg.starts_with("-W") => {
+                    options.lints.insert(Lint::parse(&arg[2..]));
+                }
+                "--output-format" => options.output_format = Some(OutputFormat::parse(&args.next().unwrap_or_else(|| print_usage()))),
+                "-o" => options.output_file = args.next(),
+                "--label-case" => options.label_case = LabelCase::parse(&args.next().unwrap_or_else(|| print_usage())),
+                "--listing" => options.listing_file = Some(args.next().unwrap_or_else(|| print_usage())),
+                "--page-length" => {
+                    options.page_length = parse_number(args.next());
+                    if options.page_length < MIN_PAGE_LENGTH {
+                        panic!("Page length {} is too short, a page needs at least {} lines", options.page_length, MIN_PAGE_LENGTH)
+                    }
+                }
+                "--symbols" => options.symbols_file = Some(args.next().unwrap_or_else(|| print_usage())),
+                "--charset" => options.charset = Charset::parse(&args.next().unwrap_or_else(|| print_usage())),
+                "--isa" => options.isa_file = Some(args.next().unwrap_or_else(|| print_usage())),
+                "--cfg" => options.cfg_file = Some(args.next().unwrap_or_else(|| print_usage())),
+                "--format" => options.format = match args.next().as_deref() {
+                    Some("listing") => ReportFormat::Listing,
+                    Some("json") => ReportFormat::Json,
+                    _ => print_usage(),
+                },
+                "--fill" => {
+                    let value = args.next().unwrap_or_else(|| print_usage());
+                    options.fill = u8::from_str_radix(&value, 16).unwrap_or_else(|_| panic!("Invalid fill byte {}, expected two hex digits", value));
+                }
+                "--check" => options.check = true,
+                "--label-width" => options.format_options.label_width = parse_number(args.next()),
+                "--opcode-width" => options.format_options.opcode_width = parse_number(args.next()),
+                "--operand-width" => options.format_options.operand_width = parse_number(args.next()),
+                "--case" => options.format_options.case = Case::parse(&args.next().unwrap_or_else(|| print_usage())),
+                _ => options.filenames.push(arg),
+            }
+        }
+        if options.filenames.is_empty() || (options.command != Command::Format && options.filenames.len() > 1) {
+            print_usage()
+        }
+        options.filename = options.filenames[0].clone();
+        options
+    }
+}
+
+fn parse_number(arg: Option<String>) -> usize {
+    let arg = arg.unwrap_or_else(|| print_usage());
+    arg.parse::<usize>().unwrap_or_else(|_| panic!("Invalid number {}", arg))
+}
+
+fn print_usage() -> ! {
+    panic!("Usage: ./sic_xe_assembler [--auto-extend] [--check-base] [--explain] [-I dir]... [-Wall] [-Wlint] [-Wno-lint] [--format listing|json]\n       [--output-format text|binary|ihex|srec] [-o file] [--fill XX] [--cfg file.dot]
+       [--label-case sensitive|insensitive] [--listing file] [--page-length N] [--symbols file]
+       [--charset ascii|ebcdic] [--isa file] <filename>||-i\n       ./sic_xe_assembler verify <object file>\n       ./sic_xe_assembler fmt [--check] [--isa file] [--label-width N] [--opcode-width N] [--operand-width N]\n       [--case upper|lower|keep] <filename>...")
+}
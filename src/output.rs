@@ -0,0 +1,117 @@
+use crate::object_program::ObjectProgram;
+
+/// Bytes per data record in Intel HEX and S-record output.
+const RECORD_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Binary,
+    IntelHex,
+    SRecord,
+}
+
+impl OutputFormat {
+    pub fn parse(format: &str) -> Self {
+        match format {
+            "text" => OutputFormat::Text,
+            "binary" => OutputFormat::Binary,
+            "ihex" => OutputFormat::IntelHex,
+            "srec" => OutputFormat::SRecord,
+            _ => panic!("Unknown output format {}, expected text, binary, ihex or srec", format)
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "obj",
+            OutputFormat::Binary => "bin",
+            OutputFormat::IntelHex => "hex",
+            OutputFormat::SRecord => "srec",
+        }
+    }
+}
+
+pub fn write_output(program: &ObjectProgram, format: OutputFormat, fill: u8) -> Vec<u8> {
+    match format {
+        OutputFormat::Text => program.to_records().into_bytes(),
+        OutputFormat::Binary => to_binary(program, fill),
+        OutputFormat::IntelHex => to_intel_hex(program).into_bytes(),
+        OutputFormat::SRecord => to_s_records(program).into_bytes(),
+    }
+}
+
+/// Flat memory image from the program start to its end, with `fill` in the gaps left by RESB/RESW.
+pub fn to_binary(program: &ObjectProgram, fill: u8) -> Vec<u8> {
+    let mut image = vec![fill; program.header.length];
+    for text in &program.texts {
+        let offset = text.start - program.header.start;
+        image[offset..offset + text.bytes.len()].copy_from_slice(&text.bytes);
+    }
+    image
+}
+
+/// Splits the text records into chunks of at most RECORD_LEN bytes that don't cross a 64K boundary.
+fn data_records(program: &ObjectProgram) -> Vec<(usize, &[u8])> {
+    let mut records = Vec::new();
+    for text in &program.texts {
+        let mut offset = 0;
+        while offset < text.bytes.len() {
+            let address = text.start + offset;
+            let len = RECORD_LEN.min(text.bytes.len() - offset).min(0x10000 - (address & 0xFFFF));
+            records.push((address, &text.bytes[offset..offset + len]));
+            offset += len;
+        }
+    }
+    records
+}
+
+fn hex_record(prefix: &str, fields: &[u8], checksum: u8) -> String {
+    let mut record = String::from(prefix);
+    for byte in fields {
+        record += &format!("{:02X}", byte);
+    }
+    record + &format!("{:02X}\n", checksum)
+}
+
+fn byte_sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |acc, byte| acc.wrapping_add(*byte))
+}
+
+/// Intel HEX with type 04 records for addresses above 64K and a type 05 start address.
+pub fn to_intel_hex(program: &ObjectProgram) -> String {
+    let intel_record = |record_type: u8, address: u16, data: &[u8]| {
+        let mut fields = vec![data.len() as u8, (address >> 8) as u8, address as u8, record_type];
+        fields.extend_from_slice(data);
+        hex_record(":", &fields, byte_sum(&fields).wrapping_neg())
+    };
+    let mut output = String::new();
+    let mut upper = 0;
+    for (address, data) in data_records(program) {
+        if address >> 16 != upper {
+            upper = address >> 16;
+            output += &intel_record(0x04, 0, &[(upper >> 8) as u8, upper as u8]);
+        }
+        output += &intel_record(0x00, address as u16, data);
+    }
+    if let Some(start) = program.first_instruction {
+        output += &intel_record(0x05, 0, &(start as u32).to_be_bytes());
+    }
+    output + &intel_record(0x01, 0, &[])
+}
+
+/// Motorola S-records using 24-bit addresses, since SIC/XE memory is 1MB: S0 header with the program
+/// name, S2 data records and an S8 record holding the first instruction.
+pub fn to_s_records(program: &ObjectProgram) -> String {
+    let s_record = |record_type: &str, address: &[u8], data: &[u8]| {
+        let mut fields = vec![(address.len() + data.len() + 1) as u8];
+        fields.extend_from_slice(address);
+        fields.extend_from_slice(data);
+        hex_record(record_type, &fields, !byte_sum(&fields))
+    };
+    let address_bytes = |address: usize| [(address >> 16) as u8, (address >> 8) as u8, address as u8];
+    let mut output = s_record("S0", &[0, 0], program.header.name.as_bytes());
+    for (address, data) in data_records(program) {
+        output += &s_record("S2", &address_bytes(address), data);
+    }
+    output + &s_record("S8", &address_bytes(program.first_instruction.unwrap_or(program.header.start)), &[])
+}
@@ -1,6 +1,8 @@
+use crate::charset;
+use crate::diagnostics::{did_you_mean, SourceLocation};
 use crate::global_map::{Constant, GlobalMap};
 use crate::nixbpe::Nixbpe;
-use crate::utils::{bin_string_to_i32, get_nth_char, hex_string_to_i32, i32_to_bin_string, i32_to_hex_string, is_valid_decimal_string, string_to_usize};
+use crate::utils::{bin_string_to_i32, get_nth_char, hex_string_to_bytes, i32_to_bin_string, i32_to_hex_string, is_valid_decimal_string, string_to_usize};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum AddressingModes {
@@ -11,6 +13,12 @@ pub enum AddressingModes {
     Direct,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Relativity {
+    Pc,
+    Base,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum OpcodeFormat {
     One,
@@ -19,12 +27,216 @@ pub enum OpcodeFormat {
     Four,
 }
 
+/// The operand an instruction expects, as listed in the opcode table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperandShape {
+    None,
+    Memory,
+    Register,
+    RegisterPair,
+    RegisterCount,
+    Count,
+}
+
+impl OperandShape {
+    pub fn parse(shape: &str) -> Self {
+        match shape.to_lowercase().as_str() {
+            "none" => OperandShape::None,
+            "memory" => OperandShape::Memory,
+            "register" => OperandShape::Register,
+            "register-pair" => OperandShape::RegisterPair,
+            "register-count" => OperandShape::RegisterCount,
+            "count" => OperandShape::Count,
+            _ => panic!("Unknown operand shape {}, expected none, memory, register, register-pair, register-count or count", shape)
+        }
+    }
+}
+
+/// Operand of a format 2 instruction, held as the values that go into the r1 and r2 nibbles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FormatTwoOperand {
+    Register(u8),
+    RegisterPair(u8, u8),
+    //the count is stored as n-1, so SHIFTL T,4 keeps 3
+    RegisterCount(u8, u8),
+    Count(u8),
+}
+
+impl FormatTwoOperand {
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            FormatTwoOperand::Register(r1) => r1 << 4,
+            FormatTwoOperand::RegisterPair(r1, r2) => (r1 << 4) | r2,
+            FormatTwoOperand::RegisterCount(r1, n) => (r1 << 4) | n,
+            FormatTwoOperand::Count(n) => n << 4,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum AddressSpec {
-    Address(usize, AddressingModes),
+    Address(i32, AddressingModes),
     Label(String, AddressingModes),
     Literal(Constant),
     Constant(Constant),
+    FormatTwo(FormatTwoOperand),
+    Words(Vec<i32>),
+    Bytes(Vec<u8>),
+    //an EQU whose value waits on labels defined further down
+    Expression(Expression),
+    //ASSERT, with the message to report when it fails
+    Condition(Condition, Option<String>),
+    //the text of ERROR and WARNING
+    Message(String),
+}
+
+impl AddressSpec {
+    fn constant_bytes(&self) -> Vec<u8> {
+        match self {
+            AddressSpec::Constant(constant) => constant.bytes(),
+            _ => panic!("Expected a constant"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    Number(i32),
+    Label(String),
+    //`*`, the location counter
+    Here,
+}
+
+/// Terms added or subtracted left to right, as in `BUFEND-BUFFER+1`. Each term is kept with
+/// whether it is subtracted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    pub terms: Vec<(bool, Term)>,
+}
+
+impl Expression {
+    pub fn labels(&self) -> impl Iterator<Item = &String> {
+        self.terms.iter().filter_map(|(_, term)| match term {
+            Term::Label(label) => Some(label),
+            _ => None,
+        })
+    }
+
+    /// The value, and whether it is absolute, or None while one of the labels isn't defined. Relative
+    /// terms have to cancel out in pairs, leaving either none (absolute) or one added (relative).
+    pub fn evaluate(&self, global_map: &GlobalMap, loc: usize) -> Option<(i32, bool)> {
+        let mut value = 0;
+        let mut relative_terms = 0;
+        for (is_subtracted, term) in &self.terms {
+            let (term_value, is_relative) = match term {
+                Term::Number(number) => (*number, false),
+                Term::Label(label) => (*global_map.label_map.get(label)? as i32, !global_map.is_absolute(label)),
+                Term::Here => (loc as i32, true),
+            };
+            let sign = if *is_subtracted { -1 } else { 1 };
+            value += sign * term_value;
+            relative_terms += if is_relative { sign } else { 0 };
+        }
+        match relative_terms {
+            0 => Some((value, true)),
+            1 => Some((value, false)),
+            _ => panic!("Relative terms in {} don't cancel out, so it is neither absolute nor relative", self),
+        }
+    }
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, (is_subtracted, term)) in self.terms.iter().enumerate() {
+            if *is_subtracted {
+                write!(f, "-")?;
+            } else if idx > 0 {
+                write!(f, "+")?;
+            }
+            match term {
+                Term::Number(number) => write!(f, "{}", number)?,
+                Term::Label(label) => write!(f, "{}", label)?,
+                Term::Here => write!(f, "*")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    //two character operators come first, so that `<=` isn't read as `<`
+    const OPERATORS: [(&'static str, Comparison); 8] = [
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<>", Comparison::NotEqual),
+        ("!=", Comparison::NotEqual),
+        ("==", Comparison::Equal),
+        ("=", Comparison::Equal),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    pub fn holds(&self, left: i32, right: i32) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        Self::OPERATORS.iter().find(|(_, comparison)| comparison == self).unwrap().0
+    }
+}
+
+/// The condition of an ASSERT: an expression that holds when it isn't zero, or two expressions
+/// compared, as in `BUFEND-BUFFER<=4096`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub left: Expression,
+    pub comparison: Option<(Comparison, Expression)>,
+}
+
+impl Condition {
+    pub fn labels(&self) -> impl Iterator<Item = &String> {
+        self.left.labels().chain(self.comparison.iter().flat_map(|(_, right)| right.labels()))
+    }
+
+    pub fn expressions_mut(&mut self) -> impl Iterator<Item = &mut Expression> {
+        std::iter::once(&mut self.left).chain(self.comparison.iter_mut().map(|(_, right)| right))
+    }
+
+    /// Whether the condition holds, or None while one of the labels isn't defined.
+    pub fn evaluate(&self, global_map: &GlobalMap, loc: usize) -> Option<bool> {
+        let (left, _) = self.left.evaluate(global_map, loc)?;
+        match &self.comparison {
+            Some((comparison, right)) => Some(comparison.holds(left, right.evaluate(global_map, loc)?.0)),
+            None => Some(left != 0),
+        }
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.left)?;
+        if let Some((comparison, right)) = &self.comparison {
+            write!(f, "{}{}", comparison.symbol(), right)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -35,13 +247,195 @@ pub enum OpcodeSpec {
 
 #[derive(Debug, Clone)]
 pub struct ASMLine {
+    pub line_no: usize,
+    pub location: SourceLocation,
+    pub label: String,
+    pub loc: usize,
     pub opcode_spec: OpcodeSpec,
     pub address_spec: AddressSpec,
 }
 
+pub const DIRECTIVES: [&str; 24] = [
+    "BASE", "NOBASE", "START", "RESW", "RESB", "WORD", "BYTE", "END", "LTORG", "EQU", "ALIGN", "FILL",
+    "TITLE", "SUBTTL", "EJECT", "SPACE", "LIST", "NOLIST", "ASSERT", "ERROR", "WARNING", "IMPORT", "CODEPAGE", "OPDEF",
+];
+
+/// The label, opcode, operand and comment columns of a source line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LineParts {
+    pub label: String,
+    pub opcode: String,
+    pub operand: String,
+    pub comment: String,
+}
+
+impl LineParts {
+    /// Comment lines and blank lines don't generate anything.
+    pub fn is_empty(&self) -> bool {
+        self.opcode.is_empty() && self.label.is_empty()
+    }
+}
+
+pub fn is_directive(word: &str) -> bool {
+    DIRECTIVES.iter().any(|directive| directive.eq_ignore_ascii_case(word))
+}
+
+pub fn is_opcode(global_map: &GlobalMap, word: &str) -> bool {
+    is_directive(word) || word.starts_with('+') || global_map.is_mnemonic(word)
+}
+
+/// Splits a line into its columns. A comment starts at a word beginning with `.` and runs to the end
+/// of the line, and so does any text after the operand. A line with two words is read as
+/// `OPCODE OPERAND` unless only the second word is an opcode, in which case it is `LABEL OPCODE`.
+pub fn split_line(global_map: &GlobalMap, line: &str) -> LineParts {
+    let mut words = Vec::new();
+    let mut comment = "";
+    let mut start = line.len() - line.trim_start().len();
+    while start < line.len() {
+        let rest = &line[start..];
+        if rest.starts_with('.') {
+            comment = rest.trim_end();
+            break;
+        }
+        let end = start + word_end(rest);
+        words.push((start, &line[start..end]));
+        start = line.len() - line[end..].trim_start().len();
+    }
+    let texts = words.iter().map(|(_, word)| *word).collect::<Vec<&str>>();
+    let (label, opcode, operand, used) = match texts.as_slice() {
+        [] => ("", "", "", 0),
+        [opcode] => ("", *opcode, "", 1),
+        [first, second] if is_opcode(global_map, first) => ("", *first, *second, 2),
+        [label, opcode] if is_opcode(global_map, opcode) => (*label, *opcode, "", 2),
+        //neither word is an opcode, so the first one is most likely a misspelt one
+        [opcode, operand] => ("", *opcode, *operand, 2),
+        [first, second, ..] if is_opcode(global_map, first) && !is_opcode(global_map, second) => ("", *first, *second, 2),
+        [label, opcode, operand, ..] => (*label, *opcode, *operand, 3),
+    };
+    //SIC programs often write comments after the operand without a `.`
+    if let Some((start, _)) = words.get(used) {
+        comment = line[*start..].trim_end();
+    }
+    LineParts { label: label.to_string(), opcode: opcode.to_string(), operand: join_list(operand), comment: comment.to_string() }
+}
+
+/// Where the word at the start of `text` ends. Whitespace ends it, except between quotes, as in
+/// `C'A B'`, next to a comma, as in `1, 2`, and before the `DUP` of a repeat count, as in `5 DUP(0)`.
+fn word_end(text: &str) -> usize {
+    let is_end = |idx: usize, c: char| c.is_whitespace() && !is_beside_comma(text, idx) && !text[idx..].trim_start().to_uppercase().starts_with("DUP(");
+    unquoted_chars(text).find(|(idx, c)| is_end(*idx, *c)).map_or(text.len(), |(idx, _)| idx)
+}
+
+fn is_beside_comma(text: &str, idx: usize) -> bool {
+    text[..idx].trim_end().ends_with(',') || text[idx..].trim_start().starts_with(',')
+}
+
+/// The operand without the whitespace around its commas, so `1, 2` reads as `1,2`.
+fn join_list(operand: &str) -> String {
+    let spaces = unquoted_chars(operand).filter(|(idx, c)| c.is_whitespace() && is_beside_comma(operand, *idx)).map(|(idx, _)| idx).collect::<Vec<usize>>();
+    operand.char_indices().filter(|(idx, _)| !spaces.contains(idx)).map(|(_, c)| c).collect()
+}
+
+/// The characters of `text` that aren't between quotes, along with their byte offsets. Between quotes
+/// `\` escapes the character after it, so `C'IT\'S'` is a single quoted string.
+fn unquoted_chars(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    text.char_indices().filter(move |(_, c)| {
+        let was_in_quotes = in_quotes;
+        if escaped {
+            escaped = false;
+        } else if in_quotes && *c == '\\' {
+            escaped = true;
+        } else if *c == '\'' {
+            in_quotes = !in_quotes;
+        }
+        !was_in_quotes && !in_quotes
+    })
+}
+
+/// Splits at commas that aren't between quotes or inside the parentheses of a repeat count.
+pub fn split_operands(operand: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, c) in unquoted_chars(operand) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&operand[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&operand[start..]);
+    parts
+}
+
+/// Whether `item` is one quoted constant. Only the qualifier is outside the quotes, which rules out
+/// lists like `X'01',X'02'`.
+fn is_constant(item: &str) -> bool {
+    item.len() >= 3 && item.starts_with(['X', 'x', 'C', 'c']) && item[1..].starts_with('\'') && item.ends_with('\'')
+        && unquoted_chars(item).count() == 1
+}
+
+/// Splits a repeated item, `n(ITEMS)` or `n DUP(ITEMS)`, into the count and what is repeated.
+fn parse_repeat(item: &str) -> Option<(usize, &str)> {
+    if is_constant(item) || !item.ends_with(')') {
+        return None;
+    }
+    let open = item.find('(')?;
+    let count = item[..open].trim_end();
+    let count = if count.to_uppercase().ends_with("DUP") { count[..count.len() - 3].trim_end() } else { count };
+    let count = count.parse::<usize>().unwrap_or_else(|_| panic!("Invalid repeat count {}", count));
+    Some((count, &item[open + 1..item.len() - 1]))
+}
+
+/// Parses a comma separated list, repeating the items of `n(...)` n times.
+fn parse_list<T: Copy>(operand: &str, parse_item: &impl Fn(&str) -> Vec<T>) -> Vec<T> {
+    split_operands(operand).into_iter().flat_map(|item| match parse_repeat(item.trim()) {
+        Some((count, repeated)) => parse_list(repeated, parse_item).repeat(count),
+        None => parse_item(item.trim()),
+    }).collect()
+}
+
+/// Parses the operand of BYTE: constants, and numbers taken as a single byte each. A lone constant
+/// is kept as one, everything else becomes a list of bytes.
+pub fn parse_byte_operand(operand: String) -> AddressSpec {
+    if is_constant(&operand) {
+        return parse_address(operand);
+    }
+    AddressSpec::Bytes(parse_list(&operand, &|item| {
+        if is_constant(item) {
+            return parse_address(item.to_string()).constant_bytes();
+        }
+        vec![parse_byte(item)]
+    }))
+}
+
+/// Parses the `count,value` operand of FILL.
+pub fn parse_fill_operand(operand: String) -> AddressSpec {
+    let [count, value] = split_operands(&operand)[..] else {
+        panic!("FILL needs a count and a value, as in FILL 10,0")
+    };
+    let count = parse_number(count).and_then(|count| usize::try_from(count).ok()).unwrap_or_else(|| panic!("Invalid FILL count {}", count));
+    AddressSpec::Bytes(vec![parse_byte(value); count])
+}
+
+fn parse_byte(value: &str) -> u8 {
+    let byte = parse_number(value).unwrap_or_else(|| panic!("Invalid byte {}, expected a number or a constant", value));
+    if !(-128..=255).contains(&byte) {
+        panic!("Value {} doesn't fit in a byte", byte)
+    }
+    (byte & 0xFF) as u8
+}
+
+/// Mnemonics and directives are kept in upper case whatever case they were written in.
 pub fn parse_opcode(global_map: &GlobalMap, opcode: String) -> OpcodeSpec {
-    let is_directive = matches!(opcode.as_str(), "BASE" | "START" | "RESW" | "RESB" | "WORD" | "BYTE" |"END" | "LTORG");
-    if is_directive {
+    let opcode = opcode.to_uppercase();
+    if is_directive(&opcode) {
         OpcodeSpec::Directive(opcode)
     } else {
         let mut opcode = opcode;
@@ -50,6 +444,7 @@ pub fn parse_opcode(global_map: &GlobalMap, opcode: String) -> OpcodeSpec {
             if first_char == '+' {
                 let opcode_format = OpcodeFormat::Four;
                 opcode = opcode.chars().skip(1).collect();
+                global_map.get_opcode_value(&opcode);
                 return OpcodeSpec::Opcode(opcode, opcode_format);
             }
         }
@@ -65,39 +460,41 @@ pub fn get_loc_inc(opcode_spec: &OpcodeSpec, address_specs: &AddressSpec) -> usi
             match directive.as_str() {
                 "START" => {
                     match address_specs {
-                        AddressSpec::Address(address, _) => {
-                            *address
+                        AddressSpec::Address(address, _) if *address >= 0 => {
+                            *address as usize
                         }
                         _ => panic!("START needs a positive integer value")
                     }
                 }
                 "RESW" => {
                     match address_specs {
-                        AddressSpec::Address(address, _) => {
-                            address * 3
+                        AddressSpec::Address(address, _) if *address >= 0 => {
+                            *address as usize * 3
                         }
                         _ => panic!("RESW needs a positive integer value")
                     }
                 }
                 "RESB" => {
                     match address_specs {
-                        AddressSpec::Address(address, _) => {
+                        AddressSpec::Address(address, _) if *address >= 0 => {
                             //we will increase loc as binary parse as hex when required
-                            *address
+                            *address as usize
                         }
                         _ => panic!("RESB needs a positive integer value")
                     }
                 }
-                "BASE" => 0,
-                "WORD" => 3,
-                "BYTE" => {
-                    if let AddressSpec::Constant(constant) = address_specs {
-                        constant.get_len()
-                    } else {
-                        panic!("Provide an constant for BYTE")
-                    }
-                }
-                "END" => 0,
+                "BASE" | "NOBASE" => 0,
+                "WORD" => match address_specs {
+                    AddressSpec::Words(words) => words.len() * 3,
+                    _ => 3,
+                },
+                "BYTE" | "FILL" => match address_specs {
+                    AddressSpec::Constant(constant) => constant.get_len(),
+                    AddressSpec::Bytes(bytes) => bytes.len(),
+                    _ => panic!("Provide an constant for {}", directive),
+                },
+                //the padding depends on the location, so pass_one_line moves LOCCTR for ALIGN itself
+                "END" | "EQU" | "ALIGN" | "ASSERT" | "ERROR" | "WARNING" => 0,
                 "LTORG" => {
                     panic!("Handle LTORG outside");
                 }
@@ -123,31 +520,27 @@ pub fn get_loc_inc(opcode_spec: &OpcodeSpec, address_specs: &AddressSpec) -> usi
     }
 }
 
-pub fn parse_address(global_map: &GlobalMap, address: String) -> AddressSpec {
+pub fn parse_address(address: String) -> AddressSpec {
     let mut addressing_mode = AddressingModes::Direct;
-    let mut address = address;
-    let comma_splitter_address: Vec<&str> = address.split(',').collect();
+    let comma_splitter_address: Vec<&str> = split_operands(&address);
     if comma_splitter_address.len() == 2 {
         let r1 = comma_splitter_address[0];
         let r2 = comma_splitter_address[1];
-        if global_map.register_map.contains_key(r1) {
-            address = i32_to_hex_string(global_map.get_reg_value(r1), 0) + &*i32_to_hex_string(global_map.get_reg_value(r2), 0);
-            AddressSpec::Address(string_to_usize(&address), AddressingModes::None)
-        } else if r2 == "X" {
-            label_or_address(global_map, &(r1.to_string()), AddressingModes::Indexed)
+        if r2.eq_ignore_ascii_case("X") {
+            label_or_address(&(r1.to_string()), AddressingModes::Indexed)
         } else {
             panic!("Invalid address {}", address)
         }
     } else if comma_splitter_address.len() == 1 {
         let address_as_vec: Vec<char> = comma_splitter_address[0].chars().collect();
-        let first_char = address_as_vec[0];
-        let second_char = if address_as_vec.len() > 1 { address_as_vec[1] } else { ' ' };
+        let first_char = address_as_vec[0].to_ascii_uppercase();
+        let second_char = if address_as_vec.len() > 1 { address_as_vec[1].to_ascii_uppercase() } else { ' ' };
         let third_char = if address_as_vec.len() > 2 { address_as_vec[2] } else { ' ' };
         let last_char = address_as_vec[address_as_vec.len() - 1];
         let mut address = comma_splitter_address[0].to_string();
         //TODO logic for literals
         if first_char == '=' {
-            if second_char != 'X' && first_char != 'C' {
+            if second_char != 'X' && second_char != 'C' {
                 panic!("Invalid literal {}", address)
             }
             if third_char != '\'' && last_char != '\'' {
@@ -170,7 +563,7 @@ pub fn parse_address(global_map: &GlobalMap, address: String) -> AddressSpec {
             addressing_mode = AddressingModes::Indirect;
             address = address_as_vec.iter().skip(1).collect();
         }
-        return label_or_address(global_map, &address, addressing_mode);
+        label_or_address(&address, addressing_mode)
     } else {
         panic!("Invalid address {}", address)
     }
@@ -178,28 +571,212 @@ pub fn parse_address(global_map: &GlobalMap, address: String) -> AddressSpec {
 
 pub fn parse_constant(qualifier: char, constant: String) -> Constant {
     match qualifier {
-        'X' => Constant::Hex(hex_string_to_i32(constant)),
-        'C' => Constant::SicString(constant),
+        'X' => {
+            let digits = if constant.len() % 2 == 1 { format!("0{}", constant) } else { constant };
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                panic!("Invalid hex constant X'{}'", digits)
+            }
+            Constant::Hex(hex_string_to_bytes(&digits))
+        }
+        'C' => Constant::SicString(constant, charset::current()),
         _ => panic!("Invalid constant {}", constant)
     }
 }
 
-fn label_or_address(global_map: &GlobalMap, address: &String, addressing_modes: AddressingModes) -> AddressSpec {
-    if is_valid_decimal_string(address) {
-        AddressSpec::Address(string_to_usize(address), addressing_modes)
-    } else if global_map.register_map.contains_key(address) {
-        let t = hex_string_to_i32(global_map.get_reg_value(address).to_string() + "0") as usize;
-        AddressSpec::Address(t, addressing_modes)
+/// Smallest and largest value of a 24 bit word, taking it as signed or unsigned.
+pub const WORD_RANGE: std::ops::RangeInclusive<i32> = -0x800000..=0xFFFFFF;
+
+pub fn parse_expression(expression: &str) -> Expression {
+    let mut terms = Vec::new();
+    let mut is_subtracted = false;
+    let mut start = 0;
+    let mut push_term = |text: &str, is_subtracted: bool| {
+        let term = if text == "*" {
+            Term::Here
+        } else if let Some(number) = parse_number(text) {
+            Term::Number(number)
+        } else if !text.is_empty() {
+            Term::Label(text.to_string())
+        } else {
+            panic!("Missing term in {}", expression)
+        };
+        terms.push((is_subtracted, term));
+    };
+    for (idx, c) in expression.char_indices() {
+        //a sign at the very start belongs to the first term
+        if (c == '+' || c == '-') && idx > 0 {
+            push_term(&expression[start..idx], is_subtracted);
+            is_subtracted = c == '-';
+            start = idx + 1;
+        }
+    }
+    push_term(&expression[start..], is_subtracted);
+    Expression { terms }
+}
+
+pub fn parse_condition(condition: &str) -> Condition {
+    let Some(start) = condition.find(['<', '>', '=', '!']) else {
+        return Condition { left: parse_expression(condition), comparison: None };
+    };
+    let (operator, comparison) = Comparison::OPERATORS.iter().find(|(operator, _)| condition[start..].starts_with(operator))
+        .unwrap_or_else(|| panic!("Invalid comparison in {}", condition));
+    let right = &condition[start + operator.len()..];
+    Condition { left: parse_expression(&condition[..start]), comparison: Some((*comparison, parse_expression(right))) }
+}
+
+/// Parses the operand of ASSERT, a condition optionally followed by a quoted message.
+pub fn parse_assert_operand(operand: String) -> AddressSpec {
+    match split_operands(&operand).as_slice() {
+        [condition] => AddressSpec::Condition(parse_condition(condition), None),
+        [condition, message] => AddressSpec::Condition(parse_condition(condition), Some(parse_quoted("ASSERT", message))),
+        _ => panic!("ASSERT takes a condition and an optional message, like ASSERT SIZE<=4096,'too big'"),
+    }
+}
+
+/// The text between the quotes of an operand like `'text'`.
+pub fn parse_quoted(directive: &str, operand: &str) -> String {
+    if operand.len() < 2 || !operand.starts_with('\'') || !operand.ends_with('\'') {
+        panic!("{} needs quoted text, like {} 'text'", directive, directive)
+    }
+    operand[1..operand.len() - 1].to_string()
+}
+
+/// Parses the operand of WORD. Numbers, and character or hex constants of at most three bytes, are
+/// each stored in a word of their own; a single label is kept as an address to be filled in.
+pub fn parse_word_operand(operand: String) -> AddressSpec {
+    let parts = split_operands(&operand);
+    let is_value = |part: &str| parse_number(part).is_some() || parse_repeat(part).is_some();
+    if parts.len() == 1 && !is_value(parts[0]) {
+        return parse_address(operand);
+    }
+    AddressSpec::Words(parse_list(&operand, &|item| {
+        let value = parse_number(item).unwrap_or_else(|| panic!("Invalid WORD value {}, expected a number or a constant", item));
+        if !WORD_RANGE.contains(&value) {
+            panic!("WORD value {} doesn't fit in 24 bits", value)
+        }
+        vec![value]
+    }))
+}
+
+pub fn parse_format_two_operand(global_map: &GlobalMap, mnemonic: &str, operand: String) -> AddressSpec {
+    let shape = global_map.get_opcode_value(mnemonic).operands;
+    let parts: Vec<&str> = operand.split(',').collect();
+    let register = |reg: &str| -> u8 {
+        if !global_map.is_register(reg) {
+            panic!("Invalid register {} for {}{}", reg, mnemonic, did_you_mean(reg, global_map.register_map.keys()))
+        }
+        global_map.get_reg_value(reg) as u8
+    };
+    let count = |count: &str, range: std::ops::RangeInclusive<usize>| -> u8 {
+        if !is_valid_decimal_string(count) {
+            panic!("Invalid count {} for {}", count, mnemonic)
+        }
+        let count = string_to_usize(count);
+        if !range.contains(&count) {
+            panic!("Count {} for {} must be between {} and {}", count, mnemonic, range.start(), range.end())
+        }
+        count as u8
+    };
+    let operand = match (shape, parts.as_slice()) {
+        (OperandShape::Register, [r1]) => FormatTwoOperand::Register(register(r1)),
+        (OperandShape::RegisterPair, [r1, r2]) => FormatTwoOperand::RegisterPair(register(r1), register(r2)),
+        (OperandShape::RegisterCount, [r1, n]) => FormatTwoOperand::RegisterCount(register(r1), count(n, 1..=16) - 1),
+        (OperandShape::Count, [n]) => FormatTwoOperand::Count(count(n, 0..=15)),
+        _ => panic!("Invalid operand {} for {}", operand, mnemonic)
+    };
+    AddressSpec::FormatTwo(operand)
+}
+
+/// Reads a number written as decimal (`-1`), hex with a `0x` prefix or `H` suffix (`0x1F`, `0FFH`),
+/// or a hex or character constant of at most three bytes (`X'FF'`, `C'A'`).
+pub fn parse_number(number: &str) -> Option<i32> {
+    let (sign, digits) = match number.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, number),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(hex) = digits.strip_suffix(['H', 'h']).filter(|hex| hex.starts_with(|c: char| c.is_ascii_digit())) {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if digits.len() >= 3 && digits[1..].starts_with('\'') && digits.ends_with('\'') && (digits.starts_with(['X', 'x', 'C', 'c'])) {
+        let qualifier = digits.chars().next()?.to_ascii_uppercase();
+        let bytes = parse_constant(qualifier, digits[2..digits.len() - 1].to_string()).bytes();
+        if bytes.len() > 3 {
+            panic!("Constant {} doesn't fit in a word", number)
+        }
+        bytes.iter().fold(0, |value, byte| (value << 8) | *byte as i32)
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse::<i32>().ok()?
+    } else {
+        return None;
+    };
+    Some(sign * value)
+}
+
+fn label_or_address(address: &String, addressing_modes: AddressingModes) -> AddressSpec {
+    if let Some(value) = parse_number(address) {
+        AddressSpec::Address(value, addressing_modes)
     } else {
         AddressSpec::Label(address.to_string(), addressing_modes)
     }
 }
 
-pub fn get_object_code(base: usize, pc: usize, global_map: &GlobalMap, asm_line: &ASMLine) -> (Option<String>, usize, usize) {
+/// Picks PC-relative addressing when the target is within -2048..=2047 of PC, otherwise base-relative
+/// when a base is in effect and the target is within 0..=4095 of it. Returns None when neither reaches.
+pub fn get_displacement(base: Option<usize>, pc: usize, target: usize) -> Option<(Relativity, i32)> {
+    let disp = target as i32 - pc as i32;
+    if (-2048..=2047).contains(&disp) {
+        return Some((Relativity::Pc, disp));
+    }
+    let disp = target as i32 - base? as i32;
+    if (0..4096).contains(&disp) {
+        return Some((Relativity::Base, disp));
+    }
+    None
+}
+
+pub fn get_base_value(global_map: &GlobalMap, address_spec: &AddressSpec) -> usize {
+    match address_spec {
+        AddressSpec::Label(label, _) => global_map.get_label_value(label),
+        AddressSpec::Address(address, _) if *address >= 0 => *address as usize,
+        _ => panic!("provide label or address for base")
+    }
+}
+
+/// A PC or base relative displacement, along with the PC or base value it was taken from.
+#[derive(Clone, Copy, Debug)]
+pub struct Displacement {
+    pub relativity: Relativity,
+    pub from: usize,
+    pub disp: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjectCode {
+    pub code: String,
+    pub nixbpe: Option<Nixbpe>,
+    pub target: Option<usize>,
+    pub displacement: Option<Displacement>,
+}
+
+impl ObjectCode {
+    fn new(code: String) -> Self {
+        Self { code, nixbpe: None, target: None, displacement: None }
+    }
+    fn with_nixbpe(code: String, nixbpe: Nixbpe, target: Option<usize>, displacement: Option<Displacement>) -> Self {
+        Self { code, nixbpe: Some(nixbpe), target, displacement }
+    }
+}
+
+pub fn get_object_code(base: Option<usize>, pc: usize, global_map: &GlobalMap, asm_line: &ASMLine) -> (Option<ObjectCode>, Option<usize>, usize) {
     let opcode_spec = &asm_line.opcode_spec;
-    let address_spec = &asm_line.address_spec;
+    //an absolute symbol is a plain number, so it's encoded as one instead of as a displacement
+    let absolute_value = match &asm_line.address_spec {
+        AddressSpec::Label(label, mode) if global_map.is_absolute(label) => Some(AddressSpec::Address(global_map.get_label_value(label) as i32, *mode)),
+        _ => None,
+    };
+    let address_spec = absolute_value.as_ref().unwrap_or(&asm_line.address_spec);
     let pc = pc + get_loc_inc(opcode_spec, address_spec);
-    println!("PC = {}",pc);
     let mut base = base;
     let mut nixbpe = Nixbpe::new();
     let opcode_code: String;
@@ -227,9 +804,22 @@ pub fn get_object_code(base: usize, pc: usize, global_map: &GlobalMap, asm_line:
     };
     let is_extended = matches!(opcode_spec, OpcodeSpec::Opcode(_, OpcodeFormat::Four));
     let is_directive = matches!(opcode_spec, OpcodeSpec::Directive(_));
+    let mut target = None;
+    let mut displacement = None;
     match address_spec {
         AddressSpec::Address(address, addressing_mode) => {
-            address_code = i32_to_bin_string(*address as i32, address_len);
+            //immediate values may be negative, and go into the field in two's complement
+            let fits = match addressing_mode {
+                _ if is_directive || address_len == 0 => true,
+                AddressingModes::Immediate => (-(1 << (address_len - 1))..1 << address_len).contains(address),
+                _ => (0..1 << address_len).contains(address),
+            };
+            if !fits {
+                panic!("Value {} doesn't fit in the {} bit address field of format {}", address, address_len, if is_extended { 4 } else { 3 })
+            }
+            target = usize::try_from(*address).ok();
+            let mask = if address_len > 0 { (1 << address_len) - 1 } else { 0 };
+            address_code = i32_to_bin_string(*address & mask, address_len);
             match addressing_mode {
                 AddressingModes::None => {
                     nixbpe.set_direct();
@@ -249,25 +839,24 @@ pub fn get_object_code(base: usize, pc: usize, global_map: &GlobalMap, asm_line:
             }
         }
         AddressSpec::Label(label, addressing_mode) => {
-            let label_loc = global_map.label_map.get(label).unwrap_or_else(|| panic!("Invalid label {}", label));
+            let label_loc = &global_map.get_label_value(label);
+            target = Some(*label_loc);
             if !is_directive {
                 if !is_extended {
-                    let mut disp = *label_loc as i32 - pc as i32;
-                    if (-2048..=2047).contains(&disp) {
-                        nixbpe.set_pc_relative();
-                        if disp < 0 {
-                            disp += 4096;
+                    let (relativity, mut disp) = get_displacement(base, pc, *label_loc).unwrap_or_else(|| panic!("Displacement  out of bounds"));
+                    let from = match relativity {
+                        Relativity::Pc => {
+                            nixbpe.set_pc_relative();
+                            pc
                         }
-                    } else {
-                        disp = *label_loc as i32 - base as i32;
-                        if (0..4096).contains(&disp) {
+                        Relativity::Base => {
                             nixbpe.set_base_relative();
-                            disp = *label_loc as i32 - base as i32;
-                        } else if is_extended {
-                            disp = *label_loc as i32;
-                        } else {
-                            panic!("Displacement  out of bounds")
+                            base.unwrap()
                         }
+                    };
+                    displacement = Some(Displacement { relativity, from, disp });
+                    if disp < 0 {
+                        disp += 4096;
                     }
                     address_code = i32_to_bin_string(disp, 12);
                 } else {
@@ -299,40 +888,45 @@ pub fn get_object_code(base: usize, pc: usize, global_map: &GlobalMap, asm_line:
         AddressSpec::Literal(_) => {
             todo!("Add literal logic")
         }
+        AddressSpec::FormatTwo(operand) => {
+            address_code = i32_to_hex_string(operand.as_byte() as i32, 2);
+        }
         AddressSpec::Constant(constant) => {
-            address_code = match constant {
-                Constant::SicString(string) => {
-                    string.as_bytes().iter().fold(String::new(), |acc, e| acc + i32_to_hex_string(*e as i32, 2).as_str())
-                }
-                Constant::Hex(hex) => {
-                    let t = i32_to_hex_string(*hex, 0);
-                    if t.len() % 2 != 0 {
-                        format!("0{}", t)
-                    } else {
-                        t
-                    }
-                }
-            }
+            address_code = constant.bytes().iter().fold(String::new(), |acc, e| acc + i32_to_hex_string(*e as i32, 2).as_str());
+        }
+        AddressSpec::Expression(expression) => {
+            panic!("Unresolved expression {}", expression)
+        }
+        //checked by check_assertions, they generate nothing
+        AddressSpec::Condition(_, _) | AddressSpec::Message(_) => {
+            address_code = String::new();
+        }
+        AddressSpec::Bytes(bytes) => {
+            address_code = bytes.iter().fold(String::new(), |acc, byte| acc + i32_to_hex_string(*byte as i32, 2).as_str());
+        }
+        AddressSpec::Words(words) => {
+            //two's complement in 24 bits
+            address_code = words.iter().fold(String::new(), |acc, word| acc + i32_to_hex_string(word & 0xFFFFFF, 6).as_str());
         }
     }
 
     match opcode_spec {
         OpcodeSpec::Directive(directive) => {
             if directive == "BASE" {
-                if let AddressSpec::Label(label, _) = address_spec {
-                    base = *global_map.label_map.get(label).unwrap_or_else(|| panic!("Invalid label {}", label));
-                    return (None, base, pc);
-                } else if let AddressSpec::Address(address, _) = address_spec {
-                    base = *address;
-                    return (None, base, pc);
-                }
-                panic!("provide label or address for base")
+                base = Some(get_base_value(global_map, address_spec));
+                return (None, base, pc);
+            }
+            if directive == "NOBASE" {
+                return (None, None, pc);
             }
-            if directive == "BYTE" {
-                return (Some(address_code), base, pc);
+            if directive == "BYTE" || directive == "FILL" {
+                return (Some(ObjectCode::new(address_code)), base, pc);
             }
             if directive == "WORD" {
-                return (Some(i32_to_hex_string(bin_string_to_i32(address_code), 6)), base, pc);
+                if let AddressSpec::Words(_) = address_spec {
+                    return (Some(ObjectCode::new(address_code)), base, pc);
+                }
+                return (Some(ObjectCode::new(i32_to_hex_string(target.unwrap_or_default() as i32, 6))), base, pc);
             }
 
             (None, base, pc)
@@ -345,21 +939,71 @@ pub fn get_object_code(base: usize, pc: usize, global_map: &GlobalMap, asm_line:
                     nixbpe.set_extended();
                     opcode_code = i32_to_bin_string((opcode >> 2) as i32, 6);
                     let object_code = i32_to_hex_string(bin_string_to_i32(opcode_code + nixbpe.as_bin_string().as_str() + &*address_code), code_len / 4);
-                    (Some(object_code), base, pc)
+                    (Some(ObjectCode::with_nixbpe(object_code, nixbpe, target, displacement)), base, pc)
                 }
                 OpcodeFormat::Three => {
                     opcode_code = i32_to_bin_string((opcode >> 2) as i32, 6);
                     let object_code = i32_to_hex_string(bin_string_to_i32(opcode_code + nixbpe.as_bin_string().as_str() + &*address_code), code_len / 4);
-                    (Some(object_code), base, pc)
+                    (Some(ObjectCode::with_nixbpe(object_code, nixbpe, target, displacement)), base, pc)
                 }
                 OpcodeFormat::Two => {
-                    (Some((i32_to_hex_string(opcode as i32, 2)) + i32_to_hex_string(bin_string_to_i32(address_code), 2).as_str()), base, pc)
+                    (Some(ObjectCode::new(i32_to_hex_string(opcode as i32, 2) + address_code.as_str())), base, pc)
                 }
                 OpcodeFormat::One => {
-                    (Some(i32_to_hex_string(opcode as i32, 2)), base, pc)
+                    (Some(ObjectCode::new(i32_to_hex_string(opcode as i32, 2))), base, pc)
                 }
             }
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_two(mnemonic: &str, operand: &str) -> FormatTwoOperand {
+        match parse_format_two_operand(&GlobalMap::init(), mnemonic, operand.to_string()) {
+            AddressSpec::FormatTwo(operand) => operand,
+            address_spec => panic!("Expected a format 2 operand, got {:?}", address_spec),
+        }
+    }
+
+    #[test]
+    fn format_two_operands() {
+        assert_eq!(format_two("SHIFTL", "T,4"), FormatTwoOperand::RegisterCount(5, 3));
+        assert_eq!(format_two("SHIFTL", "T,4").as_byte(), 0x53);
+        assert_eq!(format_two("SHIFTR", "A,16").as_byte(), 0x0F);
+        assert_eq!(format_two("SVC", "5"), FormatTwoOperand::Count(5));
+        assert_eq!(format_two("SVC", "5").as_byte(), 0x50);
+        assert_eq!(format_two("TIXR", "T"), FormatTwoOperand::Register(5));
+        assert_eq!(format_two("TIXR", "T").as_byte(), 0x50);
+        assert_eq!(format_two("CLEAR", "X").as_byte(), 0x10);
+        assert_eq!(format_two("COMPR", "A,S"), FormatTwoOperand::RegisterPair(0, 4));
+        assert_eq!(format_two("COMPR", "A,S").as_byte(), 0x04);
+    }
+
+    #[test]
+    #[should_panic(expected = "Count 0 for SHIFTL must be between 1 and 16")]
+    fn rejects_zero_shift() {
+        format_two("SHIFTL", "T,0");
+    }
+
+    #[test]
+    #[should_panic(expected = "Count 17 for SHIFTL must be between 1 and 16")]
+    fn rejects_long_shift() {
+        format_two("SHIFTL", "T,17");
+    }
+
+    #[test]
+    #[should_panic(expected = "Count 16 for SVC must be between 0 and 15")]
+    fn rejects_large_svc() {
+        format_two("SVC", "16");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid register Q for TIXR")]
+    fn rejects_unknown_registers() {
+        format_two("TIXR", "Q");
+    }
+}
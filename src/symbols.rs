@@ -0,0 +1,63 @@
+//! Symbol files, which let separately assembled absolute programs share addresses. Each line holds
+//! a label and its address in hex; blank lines and lines starting with `.` are skipped.
+use std::fs;
+use std::path::Path;
+use crate::batch::find_include;
+use crate::diagnostics::{at_location, SourceLocation};
+use crate::global_map::GlobalMap;
+use crate::parse_utils::*;
+
+/// Writes the labels defined by the program, sorted by name. Imported labels and local labels,
+/// whose names only mean something inside this program, are left out.
+pub fn write_symbols(global_map: &GlobalMap, name: &str) -> String {
+    let mut labels = global_map.label_map.iter()
+        .filter(|(label, _)| !global_map.imported_labels.contains_key(*label) && !label.contains(['$', '.']))
+        .collect::<Vec<(&String, &usize)>>();
+    labels.sort();
+    let width = labels.iter().map(|(label, _)| label.len()).max().unwrap_or_default().max(8);
+    labels.into_iter().fold(format!(". symbols of {}\n", name), |acc, (label, address)| acc + &format!("{:<width$} {:06X}\n", label, address, width = width))
+}
+
+/// Reads the labels of a symbol file along with where each one is in the file.
+pub fn read_symbols(path: &Path) -> Vec<(String, usize, SourceLocation)> {
+    let text = fs::read_to_string(path).unwrap_or_else(|_| panic!("Unable to open symbol file {}", path.display()));
+    let mut symbols = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let location = SourceLocation { file: path.display().to_string(), line_no: idx + 1 };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('.') {
+            continue;
+        }
+        let symbol = at_location(&location, || match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            [label, address] => match usize::from_str_radix(address, 16) {
+                Ok(address) => (label.to_string(), address),
+                Err(_) => panic!("Invalid address {} of {}, expected hex", address, label),
+            },
+            _ => panic!("Invalid symbol line {}, expected a label and a hex address", line),
+        });
+        symbols.push((symbol.0, symbol.1, location));
+    }
+    symbols
+}
+
+/// Defines the labels of the symbol file named by `IMPORT 'file'` as absolute addresses. The file is
+/// looked for next to the importing file, then in each `-I` directory.
+pub fn import_symbols(global_map: &mut GlobalMap, line_parts: &LineParts, location: &SourceLocation) {
+    if !line_parts.label.is_empty() {
+        panic!("IMPORT doesn't take a label")
+    }
+    let file = parse_quoted("IMPORT", &line_parts.operand);
+    let path = find_include(&file, location, &global_map.include_dirs).unwrap_or_else(|| panic!("Couldn't find symbol file {}", file));
+    for (label, address, symbol_location) in read_symbols(&path) {
+        let label = global_map.fold_label(&label);
+        if let Some(first) = global_map.imported_labels.get(&label) {
+            if global_map.label_map.get(&label) != Some(&address) {
+                panic!("{} is imported from {} as {:06X}, but was already imported from {} as {:06X}", label, symbol_location, address, first, global_map.label_map[&label])
+            }
+            continue;
+        }
+        global_map.label_map.insert(label.clone(), address);
+        global_map.absolute_labels.insert(label.clone());
+        global_map.imported_labels.insert(label, symbol_location);
+    }
+}
@@ -11,6 +11,12 @@ pub fn hex_string_to_i32(hex_string: String) -> i32 {
     i32::from_str_radix(&hex_string, 16).unwrap_or_else(|_| panic!("Invalid hex string {}", hex_string))
 }
 
+pub fn hex_string_to_bytes(hex_string: &str) -> Vec<u8> {
+    (0..hex_string.len()).step_by(2).map(|idx| {
+        u8::from_str_radix(&hex_string[idx..idx + 2], 16).unwrap_or_else(|_| panic!("Invalid hex string {}", hex_string))
+    }).collect()
+}
+
 pub fn i32_to_hex_string(val: i32, len: usize) -> String {
     let mut t = format!("{:X}", val);
     while t.len() < len {
@@ -41,4 +47,20 @@ pub fn get_nth_char(word: impl Into<String>, n: usize) -> Result<char, String> {
     } else {
         Ok(as_vec[n - 1])
     }
-}
\ No newline at end of file
+}
+
+/// Levenshtein distance: the number of single character insertions, deletions and substitutions
+/// that turn `a` into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}